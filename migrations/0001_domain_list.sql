@@ -0,0 +1,22 @@
+-- Queue of domains to crawl. Older deployments created the
+-- table without any key, duplicates there have to be merged
+-- by the operator before the key can be added.
+CREATE TABLE IF NOT EXISTS domain_list (
+    domain_url  VARCHAR NOT NULL,
+    status      VARCHAR NOT NULL DEFAULT 'new',
+    crawled_at  TIMESTAMPTZ
+);
+
+DO $$
+DECLARE
+    duplicates BIGINT;
+BEGIN
+    SELECT COUNT(*) - COUNT(DISTINCT domain_url) INTO duplicates FROM domain_list;
+    IF duplicates > 0 THEN
+        RAISE EXCEPTION 'domain_list has % duplicate rows, remove them and run migrate again',
+            duplicates;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS domain_list_domain_url_key ON domain_list (domain_url);
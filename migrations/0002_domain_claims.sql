@@ -0,0 +1,19 @@
+-- Claiming, leases and retries of crawls
+ALTER TABLE domain_list
+    ADD COLUMN IF NOT EXISTS retry_at     TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS stop_reason  VARCHAR,
+    ADD COLUMN IF NOT EXISTS claimed_by   VARCHAR,
+    ADD COLUMN IF NOT EXISTS claimed_at   TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS lease_until  TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS attempts     INTEGER NOT NULL DEFAULT 0;
+
+-- How often the domain was linked from crawled pages
+ALTER TABLE domain_list
+    ADD COLUMN IF NOT EXISTS times_seen   INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS first_seen   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ADD COLUMN IF NOT EXISTS last_seen    TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX IF NOT EXISTS domain_list_new_idx ON domain_list (retry_at)
+    WHERE status = 'new' AND crawled_at IS NULL;
+CREATE INDEX IF NOT EXISTS domain_list_lease_idx ON domain_list (lease_until)
+    WHERE status = 'processing';
@@ -0,0 +1,7 @@
+-- Fetched robots.txt keyed by scheme://host[:port]
+CREATE TABLE IF NOT EXISTS robots_cache (
+    origin      VARCHAR PRIMARY KEY,
+    status      INTEGER NOT NULL,
+    content     TEXT NOT NULL,
+    fetched_at  BIGINT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- Validators and outlinks of crawled pages for conditional recrawls
+CREATE TABLE IF NOT EXISTS pages (
+    url            VARCHAR PRIMARY KEY,
+    etag           VARCHAR,
+    last_modified  VARCHAR,
+    content_hash   BIGINT NOT NULL,
+    outlinks       TEXT NOT NULL DEFAULT '',
+    fetched_at     BIGINT NOT NULL
+);
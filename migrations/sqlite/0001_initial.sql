@@ -0,0 +1,33 @@
+-- Single node schema, timestamps are unix seconds
+CREATE TABLE IF NOT EXISTS domain_list (
+    domain_url   TEXT PRIMARY KEY,
+    status       TEXT NOT NULL DEFAULT 'new',
+    crawled_at   INTEGER,
+    retry_at     INTEGER,
+    stop_reason  TEXT,
+    claimed_by   TEXT,
+    claimed_at   INTEGER,
+    lease_until  INTEGER,
+    attempts     INTEGER NOT NULL DEFAULT 0,
+    times_seen   INTEGER NOT NULL DEFAULT 0,
+    first_seen   INTEGER NOT NULL,
+    last_seen    INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS domain_list_status_idx ON domain_list (status, retry_at);
+
+CREATE TABLE IF NOT EXISTS robots_cache (
+    origin      TEXT PRIMARY KEY,
+    status      INTEGER NOT NULL,
+    content     TEXT NOT NULL,
+    fetched_at  INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS pages (
+    url            TEXT PRIMARY KEY,
+    etag           TEXT,
+    last_modified  TEXT,
+    content_hash   INTEGER NOT NULL,
+    outlinks       TEXT NOT NULL DEFAULT '',
+    fetched_at     INTEGER NOT NULL
+);
@@ -1,20 +1,333 @@
 // Crates
+extern crate time;
 
 // Modules
 
 // Standard libs
+use std::cmp;
+use std::thread;
 use std::error::Error;
+use std::time::{ Duration, Instant };
 
 // Project libs
-use domain::{ Domain, Page };
+use domain::{ Domain, DomainOptions, Page };
 use domain::domain_url::DomainURL;
 use domain::domain_error::DomainError;
+use domain::robots_cache::RobotsCache;
+use domain::page_store::{ PageStore, PageRecord, content_hash };
+use politeness::{ HostPoliteness, PolitenessConfig };
 use log::*;
 
-pub fn crawl_domain<F>(domain_url: &str, handle_page: F)
-                       -> Result<(), DomainError>
-    where F: Fn(Page, Vec<String>)
+/*
+Why the crawl of the domain ended
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    // Nothing more to crawl
+    Finished,
+    MaxPages,
+    MaxBytes,
+    MaxTime,
+    // Error policy gave up on the domain
+    TooManyErrors,
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            StopReason::Finished => "finished",
+            StopReason::MaxPages => "max_pages",
+            StopReason::MaxBytes => "max_bytes",
+            StopReason::MaxTime => "max_time",
+            StopReason::TooManyErrors => "too_many_errors",
+        }
+    }
+}
+
+/*
+Budgets of single domain crawl, None means no limit.
+Max depth lives in DomainOptions as its enforced
+when links are queued.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct CrawlLimits {
+    pub max_pages: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub max_time: Option<Duration>,
+}
+
+impl Default for CrawlLimits {
+    fn default() -> CrawlLimits {
+        CrawlLimits {
+            max_pages: Some(10000),
+            max_bytes: Some(1024 * 1024 * 1024),
+            max_time: Some(Duration::from_secs(6 * 3600)),
+        }
+    }
+}
+
+impl CrawlLimits {
+    /*
+    Returns the budget that ran out if any
+     */
+    pub fn exceeded(&self, stats: &CrawlStats, elapsed: Duration) -> Option<StopReason> {
+        if self.max_pages.map_or(false, |max| stats.pages >= max) {
+            return Some(StopReason::MaxPages);
+        }
+        if self.max_bytes.map_or(false, |max| stats.bytes >= max) {
+            return Some(StopReason::MaxBytes);
+        }
+        if self.max_time.map_or(false, |max| elapsed >= max) {
+            return Some(StopReason::MaxTime);
+        }
+        None
+    }
+}
+
+/*
+When to give up on the domain because of errors.
+Single broken pages are only recorded and skipped.
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorPolicy {
+    // Failures in a row, None means no limit
+    pub max_consecutive: Option<usize>,
+    // Fraction of failed requests, None means no limit
+    pub max_error_rate: Option<f32>,
+    // Error rate is not checked before that many requests
+    pub min_requests: usize,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> ErrorPolicy {
+        ErrorPolicy {
+            max_consecutive: Some(10),
+            max_error_rate: Some(0.5),
+            min_requests: 50,
+        }
+    }
+}
+
+impl ErrorPolicy {
+    /*
+    Returns TooManyErrors once failures cross the thresholds
+     */
+    pub fn exceeded(&self, stats: &CrawlStats, consecutive: usize) -> Option<StopReason> {
+        if self.max_consecutive.map_or(false, |max| consecutive >= max) {
+            return Some(StopReason::TooManyErrors);
+        }
+        let requests = stats.pages + stats.failed;
+        if requests >= self.min_requests && requests > 0 {
+            let rate = stats.failed as f32 / requests as f32;
+            if self.max_error_rate.map_or(false, |max| rate >= max) {
+                return Some(StopReason::TooManyErrors);
+            }
+        }
+        None
+    }
+
+    /*
+    Errors which mean the whole domain is unreachable,
+    no point trying other URLs
+     */
+    pub fn is_systemic(&self, err: &DomainError, root: bool) -> bool {
+        err.is_dns_error() || (root && err.is_tls_error())
+    }
+}
+
+/*
+How transient failures (timeouts, 429, 503 etc.) are retried
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    // Retries of single URL, 0 disables retrying
+    pub max_retries: u32,
+    // Backoff doubles with every attempt starting from this
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // Longer Retry-After than this is not waited for
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(2000),
+            max_delay: Duration::from_millis(60000),
+            max_retry_after: Duration::from_millis(300000),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /*
+    How long to wait before the retry, None if we should
+    not retry at all. Retry-After wins over the backoff.
+     */
+    pub fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        if let Some(wait) = retry_after {
+            return if wait <= self.max_retry_after { Some(wait) } else { None };
+        }
+
+        let exp = self.base_delay * 2u32.pow(cmp::min(attempt, 16));
+        let half = millis(cmp::min(exp, self.max_delay)) / 2;
+        // Half of it random so threads dont retry in lockstep
+        let jitter = time::precise_time_ns() % (half + 1);
+        Some(Duration::from_millis(half + jitter))
+    }
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1000000) as u64
+}
+
+/*
+What happened to single URL, handed over to the page callback
+ */
+pub enum PageOutcome {
+    // Downloaded page and links pointing to other domains
+    Fetched(Page, Vec<String>),
+    // Same as during the previous crawl, nothing to analyse
+    Unchanged(Page),
+    // Forbidden by robots.txt or of unwanted type
+    Skipped(DomainError),
+    // Broken link, server error etc.
+    Failed(DomainError),
+}
+
+/*
+What happened during the crawl
+ */
+#[derive(Debug)]
+pub struct CrawlStats {
+    pub stop_reason: StopReason,
+    // Pages downloaded, unchanged ones included
+    pub pages: usize,
+    // Pages not modified since the previous crawl
+    pub unchanged: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+    // URLs that failed to download
+    pub failed: usize,
+    // URLs forbidden by robots.txt or of unwanted type
+    pub skipped: usize,
+    // Links rejected as crawler traps
+    pub traps_rejected: usize,
+    // Links deeper than max_depth
+    pub depth_truncated: usize,
+    // Times the circuit breaker paused the host
+    pub breaker_trips: usize,
+    // Delay between requests when the crawl ended
+    pub delay: Duration,
+}
+
+impl CrawlStats {
+    fn new() -> CrawlStats {
+        CrawlStats {
+            stop_reason: StopReason::Finished,
+            pages: 0,
+            unchanged: 0,
+            bytes: 0,
+            elapsed: Duration::from_secs(0),
+            failed: 0,
+            skipped: 0,
+            traps_rejected: 0,
+            depth_truncated: 0,
+            breaker_trips: 0,
+            delay: Duration::from_secs(0),
+        }
+    }
+}
+
+/*
+Crawl settings shared by all the crawler threads
+ */
+pub struct CrawlConfig {
+    // Used when robots.txt does not say anything about delay
+    pub default_delay: Duration,
+    // Bounds for Crawl-delay/Request-rate from robots.txt
+    // and for the adaptive delay
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    pub politeness: PolitenessConfig,
+    pub limits: CrawlLimits,
+    pub errors: ErrorPolicy,
+    pub retry: RetryPolicy,
+    pub domain: DomainOptions,
+}
+
+impl Default for CrawlConfig {
+    fn default() -> CrawlConfig {
+        CrawlConfig {
+            default_delay: Duration::from_millis(5000),
+            min_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_millis(60000),
+            politeness: PolitenessConfig::default(),
+            limits: CrawlLimits::default(),
+            errors: ErrorPolicy::default(),
+            retry: RetryPolicy::default(),
+            domain: DomainOptions::default(),
+        }
+    }
+}
+
+impl CrawlConfig {
+    /*
+    Delay to use for the host given what its robots.txt asks for
+     */
+    pub fn delay_for(&self, requested: Option<Duration>) -> Duration {
+        match requested {
+            None => self.default_delay,
+            Some(d) if d < self.min_delay => self.min_delay,
+            Some(d) if d > self.max_delay => self.max_delay,
+            Some(d) => d,
+        }
+    }
+
+    /*
+    Adaptive delay of the host. Starts where robots.txt or the
+    default puts it and never drops below robots Crawl-delay.
+     */
+    pub fn politeness_for(&self, host: &str, requested: Option<Duration>) -> HostPoliteness {
+        let floor = match requested {
+            Some(_) => self.delay_for(requested),
+            None => self.min_delay,
+        };
+        HostPoliteness::new(host, self.politeness, self.delay_for(requested),
+                            floor, self.max_delay)
+    }
+}
+
+/*
+What to remember about the page for the next crawl
+ */
+fn page_record(page: &Page, outlinks: Vec<String>) -> PageRecord {
+    PageRecord {
+        url: page.url.clone(),
+        etag: page.header("ETag").last().map(|v| v.to_string()),
+        last_modified: page.header("Last-Modified").last().map(|v| v.to_string()),
+        content_hash: content_hash(page.text()),
+        outlinks: outlinks,
+        fetched_at: page.fetched_at,
+    }
+}
+
+/*
+Crawls the domain handing every page over to handle_page.
+With page store given pages seen before are only
+downloaded again if they changed.
+ */
+pub fn crawl_domain<F>(domain_url: &str, config: &CrawlConfig, robots_cache: &RobotsCache,
+                       page_store: Option<&PageStore>, handle_page: F)
+                       -> Result<CrawlStats, DomainError>
+    where F: Fn(&str, PageOutcome)
 {
+    // robots.txt and sitemaps count towards the time budget too
+    let started = Instant::now();
     let durl = DomainURL::new();
 
     // Lets validate the url syntax
@@ -23,39 +336,254 @@ pub fn crawl_domain<F>(domain_url: &str, handle_page: F)
         return Err(DomainError::InvalidURL);
     }
 
-    let mut domain = Domain::new(domain_url);
+    let mut domain = Domain::with_options(domain_url, &config.domain, robots_cache);
+    if domain.robots_unavailable() {
+        return Err(DomainError::RobotsUnavailable);
+    }
+    let mut host = config.politeness_for(domain.domain, domain.crawl_delay());
+    log_info(&format!("Using {:?} delay between requests to {}",
+                      host.delay(), domain.domain));
+    domain.delay = host.delay();
+    domain.load_sitemaps(config.limits.max_time.map(|max| started + max));
+
+    let mut stats = CrawlStats::new();
+    // Failures since the last successful download
+    let mut consecutive_errors = 0;
 
     loop {
-        match domain.paths_to_visit.pop() {
-            Some(url) => {
+        stats.elapsed = started.elapsed();
+        stats.traps_rejected = domain.traps_rejected;
+        stats.depth_truncated = domain.depth_truncated;
+        stats.breaker_trips = host.trips;
+        stats.delay = host.delay();
+        // Frontier used up on the last allowed page still is a complete crawl
+        if domain.paths_to_visit.is_empty() {
+            log_info(&format!("No more links to crawl on {}", domain.domain));
+            return Ok(stats);
+        }
+        let stop = config.limits.exceeded(&stats, stats.elapsed)
+            .or(config.errors.exceeded(&stats, consecutive_errors));
+        if let Some(reason) = stop {
+            log_warn(&format!("Crawl of {} stopped: {}", domain.domain, reason.as_str()));
+            stats.stop_reason = reason;
+            return Ok(stats);
+        }
+
+        match domain.next_url() {
+            Some(next) => {
+                let url = next.url;
                 log_info(&format!("Downloading content from {}", url));
-                let page_result = domain.get_webpage(&url);
+                let previous = page_store.and_then(|store| store.load_page(&url));
+                let mut attempt = 0;
+                let page_result = loop {
+                    // Head first fetch waits as long as between pages
+                    domain.delay = host.delay();
+                    let result = domain.get_webpage_since(&url, previous.as_ref());
+                    if let Err(ref err) = result {
+                        // Server in trouble, back off
+                        if err.is_overload() {
+                            log_warn(&format!("{} is overloaded", domain.domain));
+                            host.on_overload(err.retry_after());
+                        } else if err.is_server_failure() {
+                            host.on_failure();
+                        }
+                        let wait = if err.is_transient() {
+                            config.retry.backoff(attempt, err.retry_after())
+                        } else {
+                            None
+                        };
+                        if let Some(wait) = wait {
+                            attempt += 1;
+                            log_warn(&format!("Failed to download {}: {}. Retry {} in {:?}",
+                                              url, err, attempt, wait));
+                            thread::sleep(cmp::max(wait, host.wait_time()));
+                            continue;
+                        }
+                    }
+                    break result;
+                };
                 match page_result {
                     Ok(p) => {
-                        let mut other_domains: Vec<String> = Vec::new();
-                        log_info(&format!("Downloading {} succesful", url));
-                        durl.find_all_url(&*p, &mut domain, &mut other_domains);
-                        log_info(&format!("Found {} links for current domain and {} \
-                                  pointing to different domain",
-                                 domain.paths_to_visit.len(), other_domains.len()));
-                        // Mark url as visited
+                        stats.pages += 1;
+                        consecutive_errors = 0;
+                        host.on_success(p.duration);
+                        if p.is_not_modified() {
+                            log_info(&format!("{} not modified since last crawl", url));
+                            stats.unchanged += 1;
+                            // Links are still there so keep following them
+                            if let Some(ref record) = previous {
+                                for link in &record.outlinks {
+                                    domain.add_to_visit(link);
+                                }
+                            }
+                            domain.add_visited(&url);
+                            handle_page(&url, PageOutcome::Unchanged(p));
+                        } else {
+                            let mut other_domains: Vec<String> = Vec::new();
+                            log_info(&format!("Downloading {} succesful", url));
+                            stats.bytes += p.size as u64;
+                            // Relative links are relative to where we got redirected
+                            let outlinks = durl.find_all_url(&p, &p.final_url, &mut domain,
+                                                             &mut other_domains);
+                            log_info(&format!("Found {} links for current domain and {} \
+                                      pointing to different domain",
+                                     domain.paths_to_visit.len(), other_domains.len()));
+                            // Mark url as visited
+                            domain.add_visited(&url);
+
+                            let record = page_record(&p, outlinks);
+                            if let Some(store) = page_store {
+                                store.save_page(&record);
+                            }
+                            // Server ignored our conditions but content is the same
+                            let same = previous.map_or(false, |prev| {
+                                prev.content_hash == record.content_hash
+                            });
+                            if same {
+                                stats.unchanged += 1;
+                                handle_page(&url, PageOutcome::Unchanged(p));
+                            } else {
+                                // Here page can be send to other system for keywork analysys
+                                handle_page(&url, PageOutcome::Fetched(p, other_domains));
+                            }
+                        }
+                    },
+                    Err(DomainError::SkippedURL) => {
+                        stats.skipped += 1;
                         domain.add_visited(&url);
-                        
-                        // Here page can be send to other system for keywork analysys
-                        handle_page(p, other_domains)    
+                        handle_page(&url, PageOutcome::Skipped(DomainError::SkippedURL));
+                        // Nothing was requested so no need to wait
+                        continue;
+                    },
+                    Err(err @ DomainError::UnwantedContent(_)) => {
+                        stats.skipped += 1;
+                        domain.add_visited(&url);
+                        handle_page(&url, PageOutcome::Skipped(err));
                     },
                     Err(err) => {
-                        log_err(&format!("Error: {}", err.description()));
-                        return Err(err);
+                        let root = stats.pages + stats.failed == 0;
+                        if config.errors.is_systemic(&err, root) {
+                            log_err(&format!("Error: {}", err.description()));
+                            return Err(err);
+                        }
+                        log_warn(&format!("Failed to download {}: {}", url, err));
+                        stats.failed += 1;
+                        consecutive_errors += 1;
+                        domain.add_visited(&url);
+                        handle_page(&url, PageOutcome::Failed(err));
                     },
                 }
             },
-            None => {
-                log_info(&format!("No more links to crawl on {}", domain.domain));
-                return Ok(());
-            },
+            // Checked above already
+            None => return Ok(stats),
         }
         // Lets not overload their webserver with to frequent queries
-        ::std::thread::sleep(::std::time::Duration::from_millis(5000));
+        thread::sleep(host.wait_time());
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_delay_for() {
+    let config = CrawlConfig::default();
+
+    assert_eq!(config.delay_for(None), config.default_delay);
+    assert_eq!(config.delay_for(Some(Duration::from_millis(2000))),
+               Duration::from_millis(2000));
+    assert_eq!(config.delay_for(Some(Duration::from_millis(10))), config.min_delay);
+    assert_eq!(config.delay_for(Some(Duration::from_millis(3600000))), config.max_delay);
+}
+
+#[test]
+fn test_limits_exceeded() {
+    let limits = CrawlLimits {
+        max_pages: Some(10),
+        max_bytes: Some(1000),
+        max_time: Some(Duration::from_secs(60)),
+    };
+    let mut stats = CrawlStats::new();
+    let second = Duration::from_secs(1);
+
+    assert_eq!(limits.exceeded(&stats, second), None);
+    assert_eq!(limits.exceeded(&stats, Duration::from_secs(60)), Some(StopReason::MaxTime));
+
+    stats.bytes = 1000;
+    assert_eq!(limits.exceeded(&stats, second), Some(StopReason::MaxBytes));
+
+    stats.pages = 10;
+    assert_eq!(limits.exceeded(&stats, second), Some(StopReason::MaxPages));
+
+    let unlimited = CrawlLimits { max_pages: None, max_bytes: None, max_time: None };
+    assert_eq!(unlimited.exceeded(&stats, Duration::from_secs(3600)), None);
+}
+
+#[test]
+fn test_error_policy() {
+    let policy = ErrorPolicy {
+        max_consecutive: Some(3),
+        max_error_rate: Some(0.5),
+        min_requests: 10,
+    };
+    let mut stats = CrawlStats::new();
+
+    assert_eq!(policy.exceeded(&stats, 0), None);
+    assert_eq!(policy.exceeded(&stats, 3), Some(StopReason::TooManyErrors));
+
+    // High rate but too few requests to judge
+    stats.failed = 4;
+    stats.pages = 1;
+    assert_eq!(policy.exceeded(&stats, 2), None);
+
+    stats.pages = 6;
+    assert_eq!(policy.exceeded(&stats, 0), None);
+    stats.failed = 6;
+    assert_eq!(policy.exceeded(&stats, 0), Some(StopReason::TooManyErrors));
+
+    // Broken links are never systemic
+    assert!(!policy.is_systemic(&DomainError::HttpStatus(404, None), true));
+    assert!(!policy.is_systemic(&DomainError::HttpStatus(503, None), false));
+}
+
+#[test]
+fn test_retry_backoff() {
+    let policy = RetryPolicy {
+        max_retries: 3,
+        base_delay: Duration::from_millis(1000),
+        max_delay: Duration::from_millis(3000),
+        max_retry_after: Duration::from_millis(60000),
+    };
+    let between = |d: Option<Duration>, min: u64, max: u64| {
+        let ms = millis(d.unwrap());
+        ms >= min && ms <= max
+    };
+
+    assert!(between(policy.backoff(0, None), 500, 1000));
+    assert!(between(policy.backoff(1, None), 1000, 2000));
+    // Capped by max_delay
+    assert!(between(policy.backoff(2, None), 1500, 3000));
+    assert_eq!(policy.backoff(3, None), None);
+
+    let minute = Duration::from_millis(60000);
+    assert_eq!(policy.backoff(0, Some(minute)), Some(minute));
+    assert_eq!(policy.backoff(0, Some(minute * 2)), None);
+}
+
+#[test]
+fn test_politeness_for() {
+    let config = CrawlConfig::default();
+    let second = Duration::from_millis(1000);
+
+    // Fast server can go down to min_delay
+    let mut host = config.politeness_for("example.com", None);
+    assert_eq!(host.delay(), config.default_delay);
+    for _ in 0..100 {
+        host.on_success(Duration::from_millis(1));
+    }
+    assert_eq!(host.delay(), config.min_delay);
+
+    // But never below Crawl-delay
+    let mut host = config.politeness_for("example.com", Some(second * 3));
+    assert_eq!(host.delay(), second * 3);
+    host.on_success(Duration::from_millis(1));
+    assert_eq!(host.delay(), second * 3);
+}
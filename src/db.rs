@@ -10,6 +10,10 @@ use self::r2d2_postgres::postgres::types::ToSql;
 use self::r2d2_postgres::{ PostgresConnectionManager, TlsMode };
 use self::r2d2::Pool;
 
+use domain::robots_cache::{ RobotsStore, CachedRobots };
+use domain::page_store::{ PageStore, PageRecord };
+use store::{ CrawlStore, StoreError };
+
 #[derive(Clone)]
 pub struct DBPool(pub Pool<PostgresConnectionManager>);
 
@@ -39,82 +43,218 @@ pub fn execute(pool: &DBPool, query: &str, params: &[&ToSql]) -> Result<u64, err
     }
 }
 
-pub fn prepared_stmt<T: ToSql>(pool: &DBPool, query: &str, list: &Vec<T>)
-                               -> Result<u64, error::Error> {
-    let mut modified = 0;
-    match pool.0.get() {
-        Ok(c) => {
-            match c.prepare(query) {
-                Ok(stmt) => {
-                    for elem in list {
-                        let _ = stmt.execute(&[&elem]).map_err(|err| err)
-                            .and_then(|res| Ok(modified += res));
+// Rows written by single INSERT of store_domains
+const DOMAIN_BATCH: usize = 500;
+
+/*
+Keeps robots.txt cache in the robots_cache table
+ */
+impl RobotsStore for DBPool {
+    fn load_robots(&self, key: &str) -> Option<CachedRobots> {
+        let q = "SELECT status, content, fetched_at FROM robots_cache WHERE origin=$1";
+
+        match self.0.get() {
+            Err(_) => None,
+            Ok(conn) => {
+                match conn.query(q, &[&key]) {
+                    Err(_) => None,
+                    Ok(rows) => {
+                        rows.into_iter().next().map(|row| {
+                            let status: i32 = row.get(0);
+                            CachedRobots {
+                                status: status as u32,
+                                content: row.get(1),
+                                fetched_at: row.get(2),
+                            }
+                        })
                     }
-                    Ok(modified)
-                },
-                Err(err) => Err(err)
+                }
             }
-        },
-        Err(err) => {
-            Err(error::Error::Io(io::Error::new(io::ErrorKind::Other,
-                                                err.description())))
         }
     }
-}
 
-pub fn store_domains(pool: &DBPool, list: &Vec<String>) {
-    let query = "INSERT INTO domain_list (domain_url) VALUES ($1)";
-    let _ = prepared_stmt(pool, query, &list);
+    fn save_robots(&self, key: &str, robots: &CachedRobots) {
+        let q = "INSERT INTO robots_cache (origin, status, content, fetched_at) \
+                 VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (origin) DO UPDATE SET status=EXCLUDED.status, \
+                 content=EXCLUDED.content, fetched_at=EXCLUDED.fetched_at";
+        let status = robots.status as i32;
+        let _ = execute(self, q, &[&key, &status, &robots.content, &robots.fetched_at]);
+    }
 }
 
-pub fn next_domain(pool: &DBPool) -> Option<String> {
-    let q = "SELECT domain_url FROM domain_list WHERE crawled_at is null \
-             AND status='new' LIMIT 1";
-    
-    match pool.0.get() {
-        Err(_) => None,
-        Ok(conn) => {
-            match conn.query(q, &[]) {
-                Err(_) => None,
-                Ok(row) => {
-                    let q = "UPDATE domain_list SET status='processing'  WHERE domain_url=$1";
-                    match row.into_iter().next() {
-                        None => { None },
-                        Some(url_row) => {
-                            let url: String = url_row.get(0);
-                            let _ = execute(pool, q, &[&url.as_str()]);
-                            Some(url)
-                        }
+/*
+Keeps validators, content hash and outlinks of crawled
+pages in the pages table so recrawls can be conditional
+ */
+impl PageStore for DBPool {
+    fn load_page(&self, url: &str) -> Option<PageRecord> {
+        let q = "SELECT etag, last_modified, content_hash, outlinks, fetched_at \
+                 FROM pages WHERE url=$1";
+
+        match self.0.get() {
+            Err(_) => None,
+            Ok(conn) => {
+                match conn.query(q, &[&url]) {
+                    Err(_) => None,
+                    Ok(rows) => {
+                        rows.into_iter().next().map(|row| {
+                            let content_hash: i64 = row.get(2);
+                            let outlinks: String = row.get(3);
+                            PageRecord {
+                                url: url.to_owned(),
+                                etag: row.get(0),
+                                last_modified: row.get(1),
+                                content_hash: content_hash as u64,
+                                // One link per line
+                                outlinks: outlinks.lines().map(|l| l.to_owned()).collect(),
+                                fetched_at: row.get(4),
+                            }
+                        })
                     }
                 }
             }
         }
     }
-}
 
-pub fn domain_done(pool: &DBPool, url: &str) -> Result<(),()> {
-    let q = "UPDATE domain_list SET status='done', crawled_at=NOW() \
-             WHERE domain_url=$1";
-    
-    match execute(pool, q, &[&url]) {
-        Err(_) => Err(()),
-        Ok(_) => Ok(())
+    fn save_page(&self, record: &PageRecord) {
+        let q = "INSERT INTO pages (url, etag, last_modified, content_hash, outlinks, fetched_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6) \
+                 ON CONFLICT (url) DO UPDATE SET etag=EXCLUDED.etag, \
+                 last_modified=EXCLUDED.last_modified, content_hash=EXCLUDED.content_hash, \
+                 outlinks=EXCLUDED.outlinks, fetched_at=EXCLUDED.fetched_at";
+        let content_hash = record.content_hash as i64;
+        let outlinks = record.outlinks.join("\n");
+        let _ = execute(self, q, &[&record.url, &record.etag, &record.last_modified,
+                                   &content_hash, &outlinks, &record.fetched_at]);
     }
 }
 
-pub fn domain_err(pool: &DBPool, url: &str) -> Result<(),()> {
-    let q = "UPDATE domain_list SET status='invalid', crawled_at=NOW() \
-             WHERE domain_url=$1";
-    let res = execute(pool, q, &[&url]);
-    match res {
-        Err(_) => Err(()),
-        Ok(_) => Ok(())
+fn store_err(err: error::Error) -> StoreError {
+    StoreError(err.to_string())
+}
+
+/*
+Domain queue in the domain_list table, shared by all instances
+ */
+impl CrawlStore for DBPool {
+    /*
+    Claims up to limit domains for the worker in one statement.
+    Rows locked by other instances are skipped so no domain
+    is ever handed out twice. Claim is only valid till the
+    lease runs out unless extended.
+     */
+    fn claim_domains(&self, worker: &str, limit: usize, lease_secs: i64) -> Vec<String> {
+        let q = "UPDATE domain_list SET status='processing', claimed_by=$1, claimed_at=NOW(), \
+                 lease_until=NOW() + $3::bigint * INTERVAL '1 second' \
+                 WHERE domain_url IN (\
+                     SELECT domain_url FROM domain_list WHERE crawled_at is null \
+                     AND status='new' AND (retry_at IS NULL OR retry_at < NOW()) \
+                     LIMIT $2 FOR UPDATE SKIP LOCKED) \
+                 RETURNING domain_url";
+        let limit = limit as i64;
+
+        match self.0.get() {
+            Err(_) => Vec::new(),
+            Ok(conn) => {
+                match conn.query(q, &[&worker, &limit, &lease_secs]) {
+                    Err(_) => Vec::new(),
+                    Ok(rows) => rows.into_iter().map(|row| row.get(0)).collect(),
+                }
+            }
+        }
+    }
+
+    /*
+    Heartbeat of single domain, pushes its lease
+    further while the worker still holds it
+     */
+    fn extend_lease(&self, url: &str, worker: &str, lease_secs: i64) -> Result<u64, StoreError> {
+        let q = "UPDATE domain_list SET lease_until=NOW() + $3::bigint * INTERVAL '1 second' \
+                 WHERE domain_url=$1 AND claimed_by=$2 AND status='processing'";
+
+        execute(self, q, &[&url, &worker, &lease_secs]).map_err(store_err)
+    }
+
+    /*
+    Returns domains of workers which stopped heartbeating back
+    to the queue. After max_attempts they are marked failed.
+     */
+    fn reap_expired(&self, max_attempts: i32) -> Result<u64, StoreError> {
+        let q = "UPDATE domain_list SET attempts=attempts + 1, \
+                 status=CASE WHEN attempts + 1 >= $1 THEN 'failed' ELSE 'new' END, \
+                 claimed_by=NULL, lease_until=NULL \
+                 WHERE status='processing' AND lease_until < NOW()";
+
+        execute(self, q, &[&max_attempts]).map_err(store_err)
+    }
+
+    /*
+    Marks domain as crawled along with why the crawl ended.
+    Like the rest below it is a no-op once the claim was reaped.
+     */
+    fn domain_done(&self, url: &str, worker: &str, stop_reason: &str) -> Result<(), StoreError> {
+        let q = "UPDATE domain_list SET status='done', crawled_at=NOW(), stop_reason=$3, \
+                 claimed_by=NULL, lease_until=NULL WHERE domain_url=$1 AND claimed_by=$2";
+
+        execute(self, q, &[&url, &worker, &stop_reason]).map(|_| ()).map_err(store_err)
+    }
+
+    /*
+    Put the domain back to the queue but not before the delay passes.
+    After max_attempts the domain is given up on.
+     */
+    fn domain_retry(&self, url: &str, worker: &str, delay_secs: i64, max_attempts: i32)
+                    -> Result<(), StoreError> {
+        let q = "UPDATE domain_list SET attempts=attempts + 1, \
+                 status=CASE WHEN attempts + 1 >= $4 THEN 'failed' ELSE 'new' END, \
+                 retry_at=NOW() + $3::bigint * INTERVAL '1 second', \
+                 claimed_by=NULL, lease_until=NULL WHERE domain_url=$1 AND claimed_by=$2";
+
+        execute(self, q, &[&url, &worker, &delay_secs, &max_attempts])
+            .map(|_| ()).map_err(store_err)
+    }
+
+    fn domain_err(&self, url: &str, worker: &str) -> Result<(), StoreError> {
+        let q = "UPDATE domain_list SET status='invalid', crawled_at=NOW(), \
+                 claimed_by=NULL, lease_until=NULL WHERE domain_url=$1 AND claimed_by=$2";
+
+        execute(self, q, &[&url, &worker]).map(|_| ()).map_err(store_err)
+    }
+
+    /*
+    Upserts discovered domains with how many times they were
+    seen. Known domains get the counter bumped and last_seen
+    moved, new ones are queued.
+     */
+    fn store_domains(&self, list: &[(String, i32)]) -> Result<u64, StoreError> {
+        let mut modified = 0;
+        for chunk in list.chunks(DOMAIN_BATCH) {
+            let values: Vec<String> = (0..chunk.len())
+                .map(|i| format!("(${}, ${}, NOW(), NOW())", i * 2 + 1, i * 2 + 2))
+                .collect();
+            let query = format!("INSERT INTO domain_list \
+                                 (domain_url, times_seen, first_seen, last_seen) \
+                                 VALUES {} \
+                                 ON CONFLICT (domain_url) DO UPDATE SET \
+                                 times_seen=domain_list.times_seen + EXCLUDED.times_seen, \
+                                 last_seen=EXCLUDED.last_seen", values.join(", "));
+            let mut params: Vec<&ToSql> = Vec::with_capacity(chunk.len() * 2);
+            for &(ref domain, ref count) in chunk {
+                params.push(domain);
+                params.push(count);
+            }
+            modified += try!(execute(self, &query, &params).map_err(store_err));
+        }
+        Ok(modified)
     }
 }
 
 #[cfg(test)]
 
+// Needs Postgres on localhost, run with cargo test -- --ignored
 #[test]
+#[ignore]
 fn test_db_query() {
     let pool = new_pool("postgresql://postgres@localhost", 10);
 
@@ -146,3 +286,40 @@ fn test_db_query() {
     let res = execute(&pool, "DROP TABLE test;", &[]);
     assert_eq!(res.unwrap(), 0u64);
 }
+
+// Needs Postgres on localhost, run with cargo test -- --ignored
+#[test]
+#[ignore]
+fn test_db_leases() {
+    use migrations;
+
+    let pool = new_pool("postgresql://postgres@localhost", 2);
+    migrations::migrate(&pool).unwrap();
+    let url = "http://lease-test.invalid";
+    let status = |pool: &DBPool| -> String {
+        let conn = pool.0.get().unwrap();
+        let rows = conn.query("SELECT status FROM domain_list WHERE domain_url=$1", &[&url])
+            .unwrap();
+        rows.get(0).get(0)
+    };
+
+    let _ = execute(&pool, "DELETE FROM domain_list WHERE domain_url=$1", &[&url]);
+    pool.store_domains(&[(url.to_owned(), 1)]).unwrap();
+
+    // Lease already expired
+    assert!(pool.claim_domains("test-worker", 1000, -1).contains(&url.to_owned()));
+    assert_eq!(status(&pool), "processing");
+    assert_eq!(pool.extend_lease(url, "other-worker", 600).unwrap(), 0);
+    assert_eq!(pool.extend_lease(url, "test-worker", -1).unwrap(), 1);
+    assert!(pool.reap_expired(3).unwrap() >= 1);
+    assert_eq!(status(&pool), "new");
+
+    // Reaped claim can not be finished by its former holder
+    assert!(pool.claim_domains("test-worker", 1000, 600).contains(&url.to_owned()));
+    pool.domain_done(url, "other-worker", "completed").unwrap();
+    assert_eq!(status(&pool), "processing");
+    pool.domain_retry(url, "test-worker", 3600, 3).unwrap();
+    assert_eq!(status(&pool), "new");
+
+    let _ = execute(&pool, "DELETE FROM domain_list WHERE domain_url=$1", &[&url]);
+}
@@ -0,0 +1,213 @@
+// Crates
+extern crate encoding;
+
+// Modules
+
+// Standard lib
+use std::str;
+use self::encoding::{ DecoderTrap, EncodingRef };
+use self::encoding::all::{ UTF_8, UTF_16LE, UTF_16BE, WINDOWS_1250, ISO_8859_2, WINDOWS_1252 };
+use self::encoding::label::encoding_from_whatwg_label;
+
+// Project libs
+use domain::html::tags;
+
+// How much of the document is searched for <meta charset>
+const META_PRESCAN_BYTES: usize = 1024;
+
+/*
+Tried in this order when nothing declares the charset
+and the body is not valid UTF-8. Ties go to the first,
+most of what we crawl is Central European.
+ */
+const SNIFF_ENCODINGS: [EncodingRef; 3] = [WINDOWS_1250, ISO_8859_2, WINDOWS_1252];
+
+/*
+Body decoded to UTF-8 along with the encoding used
+ */
+#[derive(Debug, PartialEq)]
+pub struct Decoded {
+    pub text: String,
+    // WHATWG name of the encoding i.e. "windows-1250"
+    pub encoding: &'static str,
+}
+
+/*
+Decodes the body following the HTML spec precedence:
+BOM, charset of Content-Type header, <meta> in the head,
+and finally sniffing the bytes
+ */
+pub fn decode(body: &[u8], content_type: Option<&str>) -> Decoded {
+    if let Some((enc, bom_len)) = from_bom(body) {
+        return decode_with(enc, &body[bom_len..]);
+    }
+
+    // Labels we dont know are ignored
+    let declared = content_type.and_then(charset_from_content_type)
+        .and_then(|label| encoding_from_whatwg_label(&label))
+        .or_else(|| {
+            charset_from_meta(body).and_then(|label| encoding_from_whatwg_label(&label))
+        });
+    if let Some(enc) = declared {
+        return decode_with(enc, body);
+    }
+
+    decode_with(sniff(body), body)
+}
+
+fn decode_with(enc: EncodingRef, body: &[u8]) -> Decoded {
+    let text = match enc.decode(body, DecoderTrap::Replace) {
+        Ok(text) => text,
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+    Decoded {
+        text: text,
+        encoding: enc.whatwg_name().unwrap_or(enc.name()),
+    }
+}
+
+fn from_bom(body: &[u8]) -> Option<(EncodingRef, usize)> {
+    if body.starts_with(b"\xEF\xBB\xBF") {
+        Some((UTF_8 as EncodingRef, 3))
+    } else if body.starts_with(b"\xFF\xFE") {
+        Some((UTF_16LE as EncodingRef, 2))
+    } else if body.starts_with(b"\xFE\xFF") {
+        Some((UTF_16BE as EncodingRef, 2))
+    } else {
+        None
+    }
+}
+
+/*
+text/html; charset="ISO-8859-2" gives iso-8859-2
+ */
+pub fn charset_from_content_type(value: &str) -> Option<String> {
+    value.split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let param = param.trim();
+            match param.find('=') {
+                Some(idx) if param[..idx].trim().eq_ignore_ascii_case("charset") => {
+                    Some(param[idx + 1..].trim().trim_matches(|c| c == '"' || c == '\''))
+                },
+                _ => None,
+            }
+        })
+        .find(|charset| !charset.is_empty())
+        .map(|charset| charset.to_lowercase())
+}
+
+/*
+Looks for <meta charset> or <meta http-equiv="Content-Type">
+at the beginning of the document
+ */
+pub fn charset_from_meta(body: &[u8]) -> Option<String> {
+    let end = if body.len() < META_PRESCAN_BYTES { body.len() } else { META_PRESCAN_BYTES };
+    let head = String::from_utf8_lossy(&body[..end]);
+
+    for tag in tags(&head) {
+        if tag.name != "meta" {
+            continue;
+        }
+        let charset = match tag.attr("charset") {
+            Some(c) => Some(c.trim().to_lowercase()),
+            None => {
+                let is_content_type = tag.attr("http-equiv")
+                    .map_or(false, |h| h.trim().eq_ignore_ascii_case("content-type"));
+                if is_content_type {
+                    tag.attr("content").and_then(charset_from_content_type)
+                } else {
+                    None
+                }
+            },
+        };
+        if let Some(c) = charset {
+            // Bytes we read are ASCII compatible so it cant really be UTF-16
+            if c.starts_with("utf-16") {
+                return Some("utf-8".to_owned());
+            }
+            return Some(c);
+        }
+    }
+    None
+}
+
+/*
+Guess for undeclared encoding. Valid UTF-8 is taken as it is,
+otherwise the candidate giving most letters and least
+symbols/control characters wins.
+ */
+fn sniff(body: &[u8]) -> EncodingRef {
+    if str::from_utf8(body).is_ok() {
+        return UTF_8;
+    }
+
+    let mut best = SNIFF_ENCODINGS[0];
+    let mut best_score = None;
+    for enc in SNIFF_ENCODINGS.iter() {
+        let text = match enc.decode(body, DecoderTrap::Strict) {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        let score = text.chars()
+            .filter(|c| !c.is_ascii())
+            .fold(0i64, |score, c| if c.is_alphabetic() { score + 1 } else { score - 1 });
+        if best_score.map_or(true, |best| score > best) {
+            best = *enc;
+            best_score = Some(score);
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+#[test]
+fn test_charset_from_content_type() {
+    assert_eq!(charset_from_content_type("text/html; charset=UTF-8"), Some("utf-8".to_owned()));
+    assert_eq!(charset_from_content_type("text/html;Charset=\"ISO-8859-2\""),
+               Some("iso-8859-2".to_owned()));
+    assert_eq!(charset_from_content_type("text/html"), None);
+    assert_eq!(charset_from_content_type("text/html; charset="), None);
+}
+
+#[test]
+fn test_charset_from_meta() {
+    assert_eq!(charset_from_meta(b"<html><head><meta charset='windows-1250'>"),
+               Some("windows-1250".to_owned()));
+    assert_eq!(charset_from_meta(b"<meta http-equiv=\"Content-Type\" \
+                                   content=\"text/html; charset=iso-8859-2\">"),
+               Some("iso-8859-2".to_owned()));
+    assert_eq!(charset_from_meta(b"<meta charset=utf-16le>"), Some("utf-8".to_owned()));
+    assert_eq!(charset_from_meta(b"<meta name='robots' content='noindex'>"), None);
+}
+
+#[test]
+fn test_decode() {
+    // "Łódź" in windows-1250
+    let cp1250 = b"\xA3\xF3d\x9F";
+
+    assert_eq!(decode(b"\xEF\xBB\xBFabc", Some("text/html; charset=windows-1250")),
+               Decoded { text: "abc".to_owned(), encoding: "utf-8" });
+    assert_eq!(decode(cp1250, Some("text/html; charset=windows-1250")).text, "Łódź");
+    assert_eq!(decode("zażółć".as_bytes(), None),
+               Decoded { text: "zażółć".to_owned(), encoding: "utf-8" });
+
+    let mut html = b"<meta charset=\"iso-8859-2\">".to_vec();
+    html.extend_from_slice(b"\xB1");
+    assert_eq!(decode(&html, None).text, "<meta charset=\"iso-8859-2\">ą");
+    // Unknown label falls through to <meta> and sniffing
+    assert_eq!(decode(&html, Some("text/html; charset=bogus")).encoding, "iso-8859-2");
+    assert_eq!(decode(b"abc", Some("text/html; charset=bogus")).encoding, "utf-8");
+}
+
+#[test]
+fn test_sniff() {
+    // "Zażółć gęślą jaźń" in windows-1250 and iso-8859-2
+    let cp1250 = b"Za\xBF\xF3\xB3\xE6 g\xEA\x9Cl\xB9 ja\x9F\xF1";
+    let latin2 = b"Za\xBF\xF3\xB3\xE6 g\xEA\xB6l\xB1 ja\xBC\xF1";
+
+    assert_eq!(decode(cp1250, None),
+               Decoded { text: "Zażółć gęślą jaźń".to_owned(), encoding: "windows-1250" });
+    assert_eq!(decode(latin2, None),
+               Decoded { text: "Zażółć gęślą jaźń".to_owned(), encoding: "iso-8859-2" });
+}
@@ -0,0 +1,118 @@
+// Crates
+
+// Modules
+
+// Standard lib
+
+// Project libs
+
+/*
+Decides what is worth downloading. Links with denied
+extensions are never queued, responses with other
+Content-Type than allowed are aborted.
+ */
+#[derive(Debug, Clone)]
+pub struct ContentFilter {
+    // MIME types handed over to the page handler
+    pub allowed_types: Vec<String>,
+    // Link extensions not worth requesting at all
+    pub denied_extensions: Vec<String>,
+    // Ask with HEAD before downloading the page
+    pub head_first: bool,
+}
+
+impl Default for ContentFilter {
+    fn default() -> ContentFilter {
+        let types = ["text/html", "application/xhtml+xml"];
+        let extensions = [
+            "jpg", "jpeg", "gif", "png", "bmp", "ico", "svg", "webp", "tif", "tiff",
+            "css", "js", "json", "woff", "woff2", "ttf", "eot",
+            "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods",
+            "zip", "gz", "tgz", "bz2", "xz", "rar", "7z", "tar", "exe", "msi", "dmg", "iso",
+            "apk", "bin", "mp3", "wav", "ogg", "flac", "mp4", "avi", "mov", "wmv", "flv",
+            "mkv", "webm",
+        ];
+        ContentFilter {
+            allowed_types: types.iter().map(|t| t.to_string()).collect(),
+            denied_extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            head_first: false,
+        }
+    }
+}
+
+impl ContentFilter {
+    /*
+    False if the URL path ends with denied extension
+     */
+    pub fn allows_url(&self, url: &str) -> bool {
+        match extension(url) {
+            Some(ext) => !self.denied_extensions.iter().any(|e| e.eq_ignore_ascii_case(&ext)),
+            None => true,
+        }
+    }
+
+    /*
+    Checks value of Content-Type header.
+    Missing header is allowed, parameters are ignored.
+     */
+    pub fn allows_type(&self, content_type: Option<&str>) -> bool {
+        let mime = match content_type {
+            Some(ct) => ct.split(';').next().unwrap_or("").trim().to_lowercase(),
+            None => return true,
+        };
+        mime.is_empty() || self.allowed_types.iter().any(|t| t.eq_ignore_ascii_case(&mime))
+    }
+}
+
+/*
+Extension of the last path segment, lowercased
+ */
+fn extension(url: &str) -> Option<String> {
+    let end = url.find(|c| c == '?' || c == '#').unwrap_or(url.len());
+    let path = &url[..end];
+    // Skip scheme and host
+    let path = match path.find("://") {
+        Some(idx) => match path[idx + 3..].find('/') {
+            Some(slash) => &path[idx + 3 + slash..],
+            None => return None,
+        },
+        None => path,
+    };
+    let segment = &path[path.rfind('/').map_or(0, |idx| idx + 1)..];
+    match segment.rfind('.') {
+        Some(idx) if idx + 1 < segment.len() => Some(segment[idx + 1..].to_lowercase()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_allows_url() {
+    let filter = ContentFilter::default();
+
+    assert!(filter.allows_url("http://example.com/"));
+    assert!(filter.allows_url("http://example.com"));
+    assert!(filter.allows_url("http://example.com/index.html"));
+    assert!(filter.allows_url("http://example.com/download?file=a.pdf"));
+    assert!(filter.allows_url("http://example.com/v1.2/page"));
+    assert!(!filter.allows_url("http://example.com/logo.PNG"));
+    assert!(!filter.allows_url("http://example.com/docs/manual.pdf?v=2#p3"));
+    assert!(!filter.allows_url("http://example.com/files/archive.tar.gz"));
+
+    let custom = ContentFilter { denied_extensions: vec!["php".to_owned()],
+                                 .. ContentFilter::default() };
+    assert!(custom.allows_url("http://example.com/logo.png"));
+    assert!(!custom.allows_url("http://example.com/index.php"));
+}
+
+#[test]
+fn test_allows_type() {
+    let filter = ContentFilter::default();
+
+    assert!(filter.allows_type(None));
+    assert!(filter.allows_type(Some("text/html")));
+    assert!(filter.allows_type(Some("Text/HTML; charset=iso-8859-2")));
+    assert!(filter.allows_type(Some("application/xhtml+xml")));
+    assert!(!filter.allows_type(Some("application/pdf")));
+    assert!(!filter.allows_type(Some("video/mp4")));
+}
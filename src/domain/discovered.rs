@@ -0,0 +1,97 @@
+// Crates
+
+// Modules
+
+// Standard lib
+use std::collections::HashMap;
+
+// Project libs
+use domain::domain_url::Origin;
+
+/*
+External domains found while crawling a domain. Each one
+is kept once, counting how many pages linked to it, so
+the database sees every domain only once per batch.
+ */
+pub struct DiscoveredDomains {
+    seen: HashMap<String, i32>,
+}
+
+impl DiscoveredDomains {
+    pub fn new() -> DiscoveredDomains {
+        DiscoveredDomains {
+            seen: HashMap::new(),
+        }
+    }
+
+    /*
+    Adds domains linked from one page. Page linking the
+    same domain several times counts once.
+     */
+    pub fn add_page(&mut self, domains: &[String]) {
+        let mut page: Vec<String> = domains.iter()
+            .filter_map(|d| normalize_domain(d))
+            .collect();
+        page.sort();
+        page.dedup();
+        for domain in page {
+            *self.seen.entry(domain).or_insert(0) += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /*
+    Hands over collected domains with their counts sorted
+    by name, so concurrent writers lock rows in the same
+    order, and starts over
+     */
+    pub fn drain(&mut self) -> Vec<(String, i32)> {
+        let mut domains: Vec<(String, i32)> = self.seen.drain().collect();
+        domains.sort();
+        domains
+    }
+}
+
+/*
+Reduces URL of the domain to scheme://host[:port], see Origin
+ */
+pub fn normalize_domain(url: &str) -> Option<String> {
+    Origin::parse(url).map(|origin| origin.to_string())
+}
+
+#[cfg(test)]
+#[test]
+fn test_normalize_domain() {
+    assert_eq!(normalize_domain("http://www.Google.com"), Some("http://www.google.com".to_owned()));
+    assert_eq!(normalize_domain("HTTP://example.com.:80"), Some("http://example.com".to_owned()));
+    assert_eq!(normalize_domain("https://user:pw@example.com:443/a"),
+               Some("https://example.com".to_owned()));
+    assert_eq!(normalize_domain("http://example.com:8080"),
+               Some("http://example.com:8080".to_owned()));
+    assert_eq!(normalize_domain("http://example.com:x"), None);
+    assert_eq!(normalize_domain("http://"), None);
+    assert_eq!(normalize_domain("ftp://example.com"), None);
+    assert_eq!(normalize_domain("example.com"), None);
+}
+
+#[test]
+fn test_discovered_domains() {
+    let mut found = DiscoveredDomains::new();
+    assert!(found.is_empty());
+
+    found.add_page(&["http://b.com".to_owned(), "http://B.com:80".to_owned(),
+                     "http://a.com".to_owned()]);
+    found.add_page(&["http://b.com".to_owned(), "mailto:".to_owned()]);
+    assert_eq!(found.len(), 2);
+
+    assert_eq!(found.drain(), vec![("http://a.com".to_owned(), 1),
+                                   ("http://b.com".to_owned(), 2)]);
+    assert!(found.is_empty());
+}
@@ -4,6 +4,7 @@ extern crate postgres;
 
 use std::fmt;
 use std::error;
+use std::time::Duration;
 /* 
 Fetching error stuct definition
  */
@@ -11,8 +12,15 @@ Fetching error stuct definition
 #[derive(Debug)]
 pub enum DomainError {
     SkippedURL,
-    RobotsError,
+    RobotsUnavailable,
     InvalidURL,
+    // Server answered with 4xx or 5xx, possibly
+    // telling us when to come back
+    HttpStatus(u32, Option<Duration>),
+    // Body exceeded configured limit
+    BodyTooLarge,
+    // Content-Type we dont crawl i.e. application/pdf
+    UnwantedContent(String),
     FetchError(curl::Error),
     DBError(postgres::error::ConnectError)
 }
@@ -21,8 +29,11 @@ impl fmt::Display for DomainError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             DomainError::SkippedURL => write!(f, "URL skipped"),
-            DomainError::RobotsError => write!(f, "robot.txt missing or malformed"),
+            DomainError::RobotsUnavailable => write!(f, "robots.txt temporarily unavailable"),
             DomainError::InvalidURL => write!(f, "Malformed url"),
+            DomainError::HttpStatus(code, _) => write!(f, "HTTP status {}", code),
+            DomainError::BodyTooLarge => write!(f, "Response body too large"),
+            DomainError::UnwantedContent(ref t) => write!(f, "Unwanted content type: {}", t),
             DomainError::FetchError(ref err) => write!(f, "Failed to grab domain: {}", err),
             DomainError::DBError(ref err) => write!(f, "DB error: {}", err),
         }
@@ -33,8 +44,11 @@ impl error::Error for DomainError {
     fn description(&self) -> &str {
         match *self {
             DomainError::SkippedURL => "URL was skipped due to robots.txt policy.",
-            DomainError::RobotsError => "robots.txt file missing or malformed.",
+            DomainError::RobotsUnavailable => "robots.txt could not be fetched. Retry later.",
             DomainError::InvalidURL => "Specified URL is invalid/malformed",
+            DomainError::HttpStatus(..) => "Server responded with error status",
+            DomainError::BodyTooLarge => "Response body exceeded the size limit",
+            DomainError::UnwantedContent(_) => "Content type is not crawled",
             DomainError::FetchError(ref err) => err.description(),
             DomainError::DBError(ref err) => err.description(),
         }
@@ -43,14 +57,89 @@ impl error::Error for DomainError {
     fn cause(&self) -> Option<&error::Error> {
         match *self {
             DomainError::SkippedURL => None,
-            DomainError::RobotsError => None,
+            DomainError::RobotsUnavailable => None,
             DomainError::InvalidURL => None,
+            DomainError::HttpStatus(..) => None,
+            DomainError::BodyTooLarge => None,
+            DomainError::UnwantedContent(_) => None,
             DomainError::FetchError(ref err) => err.cause(),
             DomainError::DBError(ref err) => err.cause(),
         }
     }
 }
 
+impl DomainError {
+    /*
+    Host name could not be resolved
+     */
+    pub fn is_dns_error(&self) -> bool {
+        match *self {
+            DomainError::FetchError(ref err) => err.is_couldnt_resolve_host(),
+            _ => false,
+        }
+    }
+
+    /*
+    Failures worth trying again after a while
+     */
+    pub fn is_transient(&self) -> bool {
+        match *self {
+            DomainError::HttpStatus(code, _) => {
+                code == 429 || code == 502 || code == 503 || code == 504
+            },
+            DomainError::FetchError(ref err) => {
+                err.is_operation_timedout() || err.is_recv_error() || err.is_send_error()
+                    || err.is_got_nothing() || err.is_partial_file()
+            },
+            _ => false,
+        }
+    }
+
+    /*
+    Server said we are sending too many requests
+     */
+    pub fn is_overload(&self) -> bool {
+        match *self {
+            DomainError::HttpStatus(code, _) => code == 429 || code == 503,
+            _ => false,
+        }
+    }
+
+    /*
+    Failures saying something about health of the server,
+    unlike 404 which is problem of the single page
+     */
+    pub fn is_server_failure(&self) -> bool {
+        match *self {
+            DomainError::HttpStatus(code, _) => code == 429 || code >= 500,
+            DomainError::FetchError(_) => true,
+            _ => false,
+        }
+    }
+
+    /*
+    Wait asked for in Retry-After header
+     */
+    pub fn retry_after(&self) -> Option<Duration> {
+        match *self {
+            DomainError::HttpStatus(_, retry_after) => retry_after,
+            _ => None,
+        }
+    }
+
+    /*
+    TLS handshake or certificate verification failed
+     */
+    pub fn is_tls_error(&self) -> bool {
+        match *self {
+            DomainError::FetchError(ref err) => {
+                err.is_ssl_connect_error() || err.is_peer_failed_verification()
+            },
+            _ => false,
+        }
+    }
+}
+
 impl From<curl::Error> for DomainError {
     fn from(err: curl::Error) -> DomainError {
         DomainError::FetchError(err)
@@ -4,14 +4,505 @@ extern crate regex;
 // Modules
 
 // Standard lib
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
 use self::regex::Regex;
 
 // Project libs
-use domain::Domain;
+use domain::{ Domain, Page };
+use domain::html::extract_links;
+use domain::frontier::fingerprint;
 use log::*;
 
+/*
+URL split into its RFC 3986 components
+ */
+#[derive(Debug, PartialEq)]
+struct UrlParts<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+    fragment: Option<&'a str>,
+}
+
+fn split_url(url: &str) -> UrlParts {
+    let (rest, fragment) = match url.find('#') {
+        Some(idx) => (&url[..idx], Some(&url[idx + 1..])),
+        None => (url, None),
+    };
+    let (rest, query) = match rest.find('?') {
+        Some(idx) => (&rest[..idx], Some(&rest[idx + 1..])),
+        None => (rest, None),
+    };
+
+    // Scheme must start with a letter and end before any "/"
+    let scheme_end = rest.find(':').and_then(|idx| {
+        let scheme = &rest[..idx];
+        let valid = scheme.chars().next().map_or(false, |c| c.is_ascii_alphabetic())
+            && scheme.chars().all(|c| c.is_ascii_alphanumeric()
+                                  || c == '+' || c == '-' || c == '.');
+        if valid { Some(idx) } else { None }
+    });
+    let (scheme, rest) = match scheme_end {
+        Some(idx) => (Some(&rest[..idx]), &rest[idx + 1..]),
+        None => (None, rest),
+    };
+
+    let (authority, path) = if rest.starts_with("//") {
+        let rest = &rest[2..];
+        match rest.find('/') {
+            Some(idx) => (Some(&rest[..idx]), &rest[idx..]),
+            None => (Some(rest), ""),
+        }
+    } else {
+        (None, rest)
+    };
+
+    UrlParts {
+        scheme: scheme,
+        authority: authority,
+        path: path,
+        query: query,
+        fragment: fragment,
+    }
+}
+
+/*
+RFC 3986 5.2.4
+ */
+pub fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output: Vec<&str> = Vec::new();
+
+    while !input.is_empty() {
+        if input.starts_with("../") {
+            input = &input[3..];
+        } else if input.starts_with("./") {
+            input = &input[2..];
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            output.pop();
+        } else if input == "/.." {
+            input = "/";
+            output.pop();
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // Move first segment including its leading "/" to output
+            let from = if input.starts_with('/') { 1 } else { 0 };
+            let end = match input[from..].find('/') {
+                Some(idx) => idx + from,
+                None => input.len(),
+            };
+            output.push(&input[..end]);
+            input = &input[end..];
+        }
+    }
+    output.concat()
+}
+
+/*
+Resolves (possibly relative) reference against base URL
+as described in RFC 3986 5.2.2
+ */
+pub fn resolve_url(base: &str, reference: &str) -> Option<String> {
+    // Browsers strip those so we do the same
+    let reference: String = reference.trim()
+        .chars()
+        .filter(|c| *c != '\n' && *c != '\r' && *c != '\t')
+        .collect();
+    let r = split_url(&reference);
+    let b = split_url(base);
+
+    let (scheme, authority, path, query) = if r.scheme.is_some() {
+        (r.scheme, r.authority, remove_dot_segments(r.path), r.query)
+    } else {
+        // Base must be absolute
+        if b.scheme.is_none() {
+            return None;
+        }
+        if r.authority.is_some() {
+            (b.scheme, r.authority, remove_dot_segments(r.path), r.query)
+        } else if r.path.is_empty() {
+            (b.scheme, b.authority, b.path.to_owned(), r.query.or(b.query))
+        } else if r.path.starts_with('/') {
+            (b.scheme, b.authority, remove_dot_segments(r.path), r.query)
+        } else {
+            // Merge with the base path
+            let merged = if b.authority.is_some() && b.path.is_empty() {
+                format!("/{}", r.path)
+            } else {
+                match b.path.rfind('/') {
+                    Some(idx) => format!("{}{}", &b.path[..idx + 1], r.path),
+                    None => r.path.to_owned(),
+                }
+            };
+            (b.scheme, b.authority, remove_dot_segments(&merged), r.query)
+        }
+    };
+
+    let mut url = String::new();
+    if let Some(s) = scheme {
+        url.push_str(s);
+        url.push(':');
+    }
+    if let Some(a) = authority {
+        url.push_str("//");
+        url.push_str(a);
+    }
+    url.push_str(&path);
+    if let Some(q) = query {
+        url.push('?');
+        url.push_str(q);
+    }
+    if let Some(f) = r.fragment {
+        url.push('#');
+        url.push_str(f);
+    }
+    Some(url)
+}
+
+/*
+What normalize_url() should do on top of the
+safe RFC 3986 normalizations
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct NormalizeOptions {
+    // ?b=1&a=2 becomes ?a=2&b=1
+    pub sort_query: bool,
+    // Drop utm_* and friends
+    pub strip_tracking: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> NormalizeOptions {
+        NormalizeOptions {
+            sort_query: true,
+            strip_tracking: true,
+        }
+    }
+}
+
+// Query parameters which only track where visitor came from
+const TRACKING_PARAMS: [&'static str; 8] = [
+    "gclid", "fbclid", "msclkid", "dclid", "yclid", "mc_cid", "mc_eid", "_ga",
+];
+
+fn is_tracking_param(param: &str) -> bool {
+    let name = match param.find('=') {
+        Some(idx) => &param[..idx],
+        None => param,
+    }.to_lowercase();
+    name.starts_with("utm_") || TRACKING_PARAMS.contains(&name.as_str())
+}
+
+/*
+Uppercase hex digits of percent escapes and
+decode the ones that encode unreserved characters
+ */
+pub fn normalize_percent_encoding(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len()
+            && (bytes[i + 1] as char).is_digit(16) && (bytes[i + 2] as char).is_digit(16) {
+            let hex = &s[i + 1..i + 3];
+            if let Ok(value) = u8::from_str_radix(hex, 16) {
+                let c = value as char;
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_' || c == '~' {
+                    out.push(c);
+                } else {
+                    out.push('%');
+                    out.push_str(&hex.to_uppercase());
+                }
+                i += 3;
+                continue;
+            }
+        }
+        // Copy whole character, might be multibyte
+        let c = s[i..].chars().next().unwrap();
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
+/*
+Scheme, host and port of http(s) URL, lowercased, without
+user info, trailing dot of the host and default port.
+Domains, seeds and robots.txt keys all come from here.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Origin {
+    pub scheme: String,
+    pub host: String,
+    // None for the default port of the scheme
+    pub port: Option<String>,
+}
+
+impl Origin {
+    pub fn parse(url: &str) -> Option<Origin> {
+        let parts = split_url(url.trim());
+        let scheme = match parts.scheme {
+            Some(s) => s.to_lowercase(),
+            None => return None,
+        };
+        let default_port = match scheme.as_str() {
+            "http" => "80",
+            "https" => "443",
+            _ => return None,
+        };
+        let authority = match parts.authority {
+            Some(a) => a,
+            None => return None,
+        };
+
+        let hostport = &authority[authority.rfind('@').map_or(0, |at| at + 1)..];
+        // IPv6 literal keeps its colons inside brackets
+        let (host, port) = match hostport.rfind(':') {
+            Some(colon) if !hostport.ends_with(']') => (&hostport[..colon], &hostport[colon + 1..]),
+            _ => (hostport, ""),
+        };
+        let host = host.trim_right_matches('.').to_lowercase();
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_';
+        let ipv6 = host.starts_with('[') && host.ends_with(']');
+        if host.is_empty() || !(ipv6 || host.chars().all(valid)) {
+            return None;
+        }
+        if !port.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(Origin {
+            scheme: scheme,
+            host: host,
+            port: if port.is_empty() || port == default_port {
+                None
+            } else {
+                Some(port.to_owned())
+            },
+        })
+    }
+
+    /*
+    Port the server listens on, explicit or the default one
+     */
+    pub fn effective_port(&self) -> &str {
+        match self.port {
+            Some(ref port) => port,
+            None if self.scheme == "https" => "443",
+            None => "80",
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.port {
+            Some(ref port) => write!(f, "{}://{}:{}", self.scheme, self.host, port),
+            None => write!(f, "{}://{}", self.scheme, self.host),
+        }
+    }
+}
+
+/*
+Brings URL to canonical form so the same page is not
+crawled twice under different names.
+Returns None for relative or non http(s) URLs.
+ */
+pub fn normalize_url(url: &str, options: &NormalizeOptions) -> Option<String> {
+    let parts = split_url(url.trim());
+    let origin = match Origin::parse(url) {
+        Some(origin) => origin,
+        None => return None,
+    };
+    // Page may need the user info, it is case sensitive so kept as is
+    let userinfo = parts.authority
+        .and_then(|a| a.rfind('@').map(|idx| &a[..idx + 1]))
+        .unwrap_or("");
+
+    let path = normalize_percent_encoding(&remove_dot_segments(parts.path));
+    let path = if path.is_empty() { "/".to_owned() } else { path };
+
+    let query = parts.query.map(|q| {
+        let mut params: Vec<String> = q.split('&')
+            .filter(|p| !p.is_empty())
+            .filter(|p| !(options.strip_tracking && is_tracking_param(p)))
+            .map(normalize_percent_encoding)
+            .collect();
+        if options.sort_query {
+            params.sort();
+        }
+        params.join("&")
+    });
+
+    let host = match origin.port {
+        Some(ref port) => format!("{}:{}", origin.host, port),
+        None => origin.host.clone(),
+    };
+    let mut normalized = format!("{}://{}{}{}", origin.scheme, userinfo, host, path);
+    match query {
+        Some(ref q) if !q.is_empty() => {
+            normalized.push('?');
+            normalized.push_str(q);
+        },
+        _ => {},
+    }
+    // Fragment never reaches the server so its dropped
+    Some(normalized)
+}
+
+/*
+Thresholds for crawler trap detection
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct TrapOptions {
+    pub max_url_length: usize,
+    pub max_query_params: usize,
+    // Same path segment showing up this many times i.e. /a/b/a/b/a/b
+    pub max_segment_repeats: usize,
+    // Distinct values of single parameter for the same path template,
+    // URLs bringing yet another value are rejected i.e. endless calendar
+    pub max_param_values: usize,
+    // URLs scoring this or more are rejected, lower non zero
+    // scores only get deprioritized
+    pub reject_score: u32,
+}
+
+impl Default for TrapOptions {
+    fn default() -> TrapOptions {
+        TrapOptions {
+            max_url_length: 2048,
+            max_query_params: 12,
+            max_segment_repeats: 3,
+            max_param_values: 200,
+            reject_score: 2,
+        }
+    }
+}
+
+// Session ids tend to show up under those names
+const SESSION_PARAMS: [&'static str; 6] = [
+    "jsessionid", "phpsessid", "sessionid", "session_id", "sid", "aspsessionid",
+];
+
+/*
+Scores URLs for patterns typical for crawler traps.
+Remembers parameter values seen per path template so
+endless calendars and faceted search get noticed.
+ */
+#[derive(Debug)]
+pub struct TrapDetector {
+    options: TrapOptions,
+    param_values: HashMap<String, HashSet<u64>>,
+}
+
+impl TrapDetector {
+    pub fn new(options: TrapOptions) -> TrapDetector {
+        TrapDetector {
+            options: options,
+            param_values: HashMap::new(),
+        }
+    }
+
+    pub fn is_trap(&self, score: u32) -> bool {
+        score >= self.options.reject_score
+    }
+
+    /*
+    0 means URL looks fine, higher means more suspicious
+     */
+    pub fn score(&mut self, url: &str) -> u32 {
+        let parts = split_url(url);
+        let mut score = 0;
+
+        if url.len() > self.options.max_url_length {
+            score += 2;
+        }
+
+        // Repeating path segments
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for segment in parts.path.split('/').filter(|s| !s.is_empty()) {
+            *counts.entry(segment).or_insert(0) += 1;
+        }
+        if counts.values().any(|&c| c >= self.options.max_segment_repeats) {
+            score += 2;
+        }
+
+        if has_session_segment(parts.path) {
+            score += 2;
+        }
+
+        let params: Vec<&str> = parts.query
+            .map(|q| q.split('&').filter(|p| !p.is_empty()).collect())
+            .unwrap_or(Vec::new());
+        if params.len() > self.options.max_query_params {
+            score += 1;
+        }
+
+        let template = path_template(parts.path);
+        for param in params {
+            let (name, value) = match param.find('=') {
+                Some(idx) => (&param[..idx], &param[idx + 1..]),
+                None => (param, ""),
+            };
+            let name = name.to_lowercase();
+            if SESSION_PARAMS.contains(&name.as_str()) {
+                score += 2;
+                continue;
+            }
+
+            let values = self.param_values
+                .entry(format!("{}?{}", template, name))
+                .or_insert(HashSet::new());
+            // No need to remember more once its over the limit
+            if values.len() <= self.options.max_param_values {
+                values.insert(fingerprint(value));
+            }
+            if values.len() > self.options.max_param_values {
+                score += 2;
+            }
+        }
+        score
+    }
+}
+
+/*
+Session id in path i.e. /page;jsessionid=123 or /sid=123/page.
+Only whole segments or ;parameters count so /sidebar is fine.
+ */
+fn has_session_segment(path: &str) -> bool {
+    path.split('/')
+        .flat_map(|segment| segment.split(';'))
+        .filter_map(|param| param.find('=').map(|idx| param[..idx].to_lowercase()))
+        .any(|name| SESSION_PARAMS.contains(&name.as_str()))
+}
+
+/*
+Path with numeric segments replaced so /item/1 and /item/2
+are treated as the same template
+ */
+fn path_template(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            if !segment.is_empty() && segment.chars().all(|c| c.is_ascii_digit()) {
+                "*"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
 pub struct DomainURL {
-    re_full_url: Regex,
     re_domain: Regex,
     re_is_url: Regex,
 }
@@ -20,11 +511,8 @@ impl DomainURL {
 
     pub fn new() -> DomainURL {
         DomainURL {
-            // This patter should grap entire URL along with its path
-            re_full_url: Regex::new(r###"(https?://[^'"?&><]*)"###).unwrap(),
-            
             // Extract only the domain part.
-            re_domain: Regex::new(r###"(https?://[^/'"?&]*)"###).unwrap(),
+            re_domain: Regex::new(r###"(https?://[^/'"?&#]*)"###).unwrap(),
             
             // Validate if string is a valid URL i.e http://www.example.com
             re_is_url: Regex::new(r###"^https?://(?:www\.)?[a-z0-9]+[^/'"?&]\.[a-z\.]{2,5}/{0,1}$"###).unwrap(),
@@ -37,47 +525,61 @@ impl DomainURL {
         }
     }
 
-    fn get_url<'a>(&self, s: &'a str) -> Option<String> {
-        match self.re_full_url.captures(s) {
-            Some(pattern) => Some(pattern[1].to_owned()),
-            _ => None,
+    /*
+    Extracts links from the page, resolves them against
+    the page URL (or its <base href>) and sorts them into
+    links of the domain we crawl and other domains.
+    Returns the links of our domain so they can be stored.
+     */
+    pub fn find_all_url(&self, page: &Page, page_url: &str,
+                        dom: &mut Domain, other: &mut Vec<String>) -> Vec<String> {
+        let mut own_links = Vec::new();
+        if !page.may_follow() {
+            log_info("Page asked not to follow its links...skipping");
+            return own_links;
         }
-    }
+        log_info(&format!("Parsing {} for links", page_url));
+
+        let own_domain = self.get_domain_part(&dom.normalize(dom.domain));
+        let links = extract_links(page.text());
+        let base = match links.base {
+            Some(ref base) => resolve_url(page_url, base).unwrap_or(page_url.to_owned()),
+            None => page_url.to_owned(),
+        };
 
-    pub fn find_all_url(&self, page: &str, dom: &mut Domain, other: &mut Vec<String>) {
-        log_info(&format!("Parsing {} for links", page));
-        for capture in self.re_full_url.captures_iter(page) {
-            if let Some(cap) = capture.at(1) {
-                /* Skip all urls ending with
-                .jpg, js, .pdf, .css etc.
-                there must be a better way to do this
+        for link in links.links {
+            if link.nofollow {
+                log_info(&format!("URL: {} is nofollow...skipping", link.href));
+                continue;
+            }
+            // Jump within the same page
+            if link.href.starts_with('#') {
+                continue;
+            }
+            // Anything other than http(s) i.e. mailto: or javascript: is dropped
+            let url = match resolve_url(&base, &link.href)
+                .and_then(|url| normalize_url(&url, &dom.normalize_options)) {
+                Some(url) => url,
+                None => continue,
+            };
+
+            if let Some(dpart) = self.get_domain_part(&url) {
+                /* 
+                Also check if URL belongs to the domain we are crawling right now.
                  */
-                if cap.ends_with(".jpg") | cap.ends_with(".gif")
-                    | cap.ends_with(".js") | cap.ends_with(".css")
-                    | cap.ends_with(".png") {
-                        log_warn(&format!("URL: {} ends with invalid extension...skipping",
-                                          cap));
-                    } else {
-                        if let Some(dpart) = self.get_domain_part(cap) {
-                            /* 
-                            Also check if URL belongs to the domain we are crawling right now.
-                             */
-                            if dpart == dom.domain {
-                                log_info(&format!("Adding new URL: \"{}\"", cap));
-                                dom.add_to_visit(cap);
-                            } else {
-                                // Extract only the domain part of URL
-                                if let Some(domain_url) = self.get_domain_part(cap) {
-                                    log_info(&format!("Found URL for external domain: \"{}\"",
-                                                      domain_url));
-                                    other.push(domain_url.to_string());
-                                }
-                            }
-                        }
-                            
+                if Some(&dpart) == own_domain.as_ref() {
+                    log_info(&format!("Adding new URL: \"{}\"", url));
+                    dom.add_to_visit(&url);
+                    if !own_links.contains(&url) {
+                        own_links.push(url);
                     }
-            }   
+                } else {
+                    log_info(&format!("Found URL for external domain: \"{}\"", dpart));
+                    other.push(dpart);
+                }
+            }
         }
+        own_links
     }
 
     pub fn is_url(&self, s: &str) -> bool {
@@ -98,23 +600,6 @@ fn test_get_domain() {
     assert_eq!(dom.get_domain_part(astring), None);
 }
 
-#[test]
-fn test_get_url() {
-
-    let dom = DomainURL::new();
-    
-    let astring = "<a href='http://www.example.com/dddddd/gggggg?param=3455' >link</a>";
-    assert_eq!(dom.get_url(astring),
-               Some("http://www.example.com/dddddd/gggggg".to_string()));
-
-    let astring = "<a href='http://www.t.b/dddddd/g(g)gggg?param=3455' >link</a>";
-    assert_eq!(dom.get_url(astring),
-               Some("http://www.t.b/dddddd/g(g)gggg".to_string()));
-
-    let astring = "<a href='' >link</a>";
-    assert_eq!(dom.get_domain_part(astring), None);
-}
-
 #[test]
 fn test_is_domain() {
     let dom = DomainURL::new();
@@ -134,3 +619,179 @@ fn test_is_domain() {
     let astring = "https://example.co.uk/";
     assert_eq!(dom.is_url(astring), true);
 }
+
+#[test]
+fn test_resolve_url() {
+    // Examples from RFC 3986 5.4
+    let base = "http://a/b/c/d;p?q";
+    let cases = [
+        ("g:h", "g:h"),
+        ("g", "http://a/b/c/g"),
+        ("./g", "http://a/b/c/g"),
+        ("g/", "http://a/b/c/g/"),
+        ("/g", "http://a/g"),
+        ("//g", "http://g"),
+        ("?y", "http://a/b/c/d;p?y"),
+        ("g?y", "http://a/b/c/g?y"),
+        ("#s", "http://a/b/c/d;p?q#s"),
+        ("g#s", "http://a/b/c/g#s"),
+        (";x", "http://a/b/c/;x"),
+        ("", "http://a/b/c/d;p?q"),
+        (".", "http://a/b/c/"),
+        ("./", "http://a/b/c/"),
+        ("..", "http://a/b/"),
+        ("../g", "http://a/b/g"),
+        ("../..", "http://a/"),
+        ("../../g", "http://a/g"),
+        ("../../../g", "http://a/g"),
+        ("/./g", "http://a/g"),
+        ("/../g", "http://a/g"),
+        ("g.", "http://a/b/c/g."),
+        ("..g", "http://a/b/c/..g"),
+        ("./../g", "http://a/b/g"),
+        ("g/./h", "http://a/b/c/g/h"),
+        ("g/../h", "http://a/b/c/h"),
+        ("g?y/../x", "http://a/b/c/g?y/../x"),
+        ("http:g", "http:g"),
+    ];
+    for &(reference, expected) in cases.iter() {
+        assert_eq!(resolve_url(base, reference), Some(expected.to_owned()));
+    }
+
+    assert_eq!(resolve_url("http://example.com", "about"),
+               Some("http://example.com/about".to_owned()));
+    assert_eq!(resolve_url("http://example.com/a/b.html", " /c\n "),
+               Some("http://example.com/c".to_owned()));
+    assert_eq!(resolve_url("relative/base", "page"), None);
+}
+
+#[test]
+fn test_find_all_url() {
+    let durl = DomainURL::new();
+    let mut dom = Domain::new("http://exampdssdsdle.com");
+    let mut other = Vec::new();
+    let html = "<a href='/about'>about</a>\
+                <a href=\"page2.html\">2</a>\
+                <a href='#top'>top</a>\
+                <a href='mailto:me@exampdssdsdle.com'>mail</a>\
+                <a href='/logo.png'>logo</a>\
+                <a rel='nofollow' href='/login'>login</a>\
+                <script>var u = 'http://exampdssdsdle.com/script';</script>\
+                <a href='HTTP://EXAMPDSSDSDLE.COM/about#team'>about again</a>\
+                <a href='http://other.com/x'>other</a>";
+    let page = Page::new("http://exampdssdsdle.com/dir/index.html", 200, Vec::new(),
+                         html.as_bytes().to_vec());
+
+    let own = durl.find_all_url(&page, "http://exampdssdsdle.com/dir/index.html",
+                                &mut dom, &mut other);
+    assert_eq!(own.len(), 3);
+    let found: Vec<String> = (0..4).filter_map(|_| dom.next_url()).map(|q| q.url).collect();
+    assert_eq!(found, vec!["http://exampdssdsdle.com/",
+                           "http://exampdssdsdle.com/about",
+                           "http://exampdssdsdle.com/dir/page2.html"]);
+    assert_eq!(other, vec!["http://other.com".to_owned()]);
+}
+
+#[test]
+fn test_normalize_url() {
+    let options = NormalizeOptions::default();
+    let norm = |url: &str| normalize_url(url, &options);
+    let expected = Some("http://example.com/a".to_owned());
+
+    assert_eq!(norm("http://Example.com/a"), expected);
+    assert_eq!(norm("HTTP://example.com:80/a"), expected);
+    assert_eq!(norm("http://example.com/a#top"), expected);
+    assert_eq!(norm("http://example.com/./a"), expected);
+    assert_eq!(norm("http://example.com/b/../a"), expected);
+    assert_eq!(norm("http://example.com/%61"), expected);
+    assert_eq!(norm("http://example.com/a?"), expected);
+    assert_eq!(norm("http://example.com/a?utm_source=x&utm_medium=y"), expected);
+
+    assert_eq!(norm("http://example.com"), Some("http://example.com/".to_owned()));
+    assert_eq!(norm("https://example.com:443/"), Some("https://example.com/".to_owned()));
+    assert_eq!(norm("http://example.com:8080/"), Some("http://example.com:8080/".to_owned()));
+    assert_eq!(norm("http://example.com/A%2fb%c5%82"),
+               Some("http://example.com/A%2Fb%C5%82".to_owned()));
+    assert_eq!(norm("http://example.com/a?b=1&a=2&gclid=3"),
+               Some("http://example.com/a?a=2&b=1".to_owned()));
+    assert_eq!(norm("http://example.com/a?b=1&a=2"), norm("http://example.com/a?a=2&b=1"));
+    assert_eq!(norm("http://example.com/100%"), Some("http://example.com/100%".to_owned()));
+    assert_eq!(norm("http://example.com/%\u{142}"), Some("http://example.com/%\u{142}".to_owned()));
+
+    assert_eq!(norm("/relative"), None);
+    assert_eq!(norm("mailto:me@example.com"), None);
+    assert_eq!(norm(""), None);
+
+    assert_eq!(norm("http://Example.com.:/a"), expected);
+    assert_eq!(norm("http://u:P@Example.com/a"), Some("http://u:P@example.com/a".to_owned()));
+    assert_eq!(norm("http://exa mple.com/a"), None);
+
+    let options = NormalizeOptions { sort_query: false, strip_tracking: false };
+    assert_eq!(normalize_url("http://example.com/a?b=1&utm_source=x&a=2", &options),
+               Some("http://example.com/a?b=1&utm_source=x&a=2".to_owned()));
+}
+
+#[test]
+fn test_origin() {
+    let origin = Origin::parse("HTTPS://user:pw@Example.com.:8443/a?b#c").unwrap();
+    assert_eq!(origin, Origin {
+        scheme: "https".to_owned(),
+        host: "example.com".to_owned(),
+        port: Some("8443".to_owned()),
+    });
+    assert_eq!(origin.to_string(), "https://example.com:8443");
+
+    let origin = Origin::parse("http://[::1]:80").unwrap();
+    assert_eq!(origin.to_string(), "http://[::1]");
+    assert_eq!(origin.effective_port(), "80");
+
+    assert_eq!(Origin::parse("http://example.com:x"), None);
+    assert_eq!(Origin::parse("http://"), None);
+    assert_eq!(Origin::parse("ftp://example.com"), None);
+    assert_eq!(Origin::parse("example.com"), None);
+}
+
+#[test]
+fn test_trap_detector() {
+    let mut traps = TrapDetector::new(TrapOptions::default());
+
+    assert_eq!(traps.score("http://example.com/a/b/c?x=1"), 0);
+    assert_eq!(traps.score("http://example.com/2016/01/01/post"), 0);
+    assert_eq!(traps.score("http://example.com/sidebar/basid=1;fsid=2"), 0);
+    let score = traps.score("http://example.com/sid=0123456789/page");
+    assert!(traps.is_trap(score));
+
+    let score = traps.score("http://example.com/a/b/a/b/a/b/");
+    assert!(traps.is_trap(score));
+    let score = traps.score("http://example.com/shop;jsessionid=0123456789ABCDEF");
+    assert!(traps.is_trap(score));
+    let score = traps.score("http://example.com/shop?PHPSESSID=0123456789abcdef");
+    assert!(traps.is_trap(score));
+    let long = format!("http://example.com/{}", "x".repeat(3000));
+    let score = traps.score(&long);
+    assert!(traps.is_trap(score));
+
+    // Too many parameters alone is only suspicious
+    let query: Vec<String> = (0..20).map(|i| format!("p{}={}", i, i)).collect();
+    let score = traps.score(&format!("http://example.com/search?{}", query.join("&")));
+    assert_eq!(score, 1);
+    assert!(!traps.is_trap(score));
+}
+
+#[test]
+fn test_trap_cardinality() {
+    let options = TrapOptions { max_param_values: 10, .. TrapOptions::default() };
+    let mut traps = TrapDetector::new(options);
+
+    // Same template as only the number in path differs
+    for month in 0..10 {
+        let url = format!("http://example.com/calendar/{}?month={}", month, month);
+        assert_eq!(traps.score(&url), 0);
+    }
+    let score = traps.score("http://example.com/calendar/1?month=10");
+    assert!(traps.is_trap(score));
+    let score = traps.score("http://example.com/calendar/1?month=1");
+    assert!(traps.is_trap(score));
+    assert_eq!(traps.score("http://example.com/other?month=11"), 0);
+    assert_eq!(path_template("/calendar/2016/page"), "/calendar/*/page");
+}
@@ -0,0 +1,435 @@
+// Crates
+
+// Modules
+
+// Standard lib
+use std::cmp::Ordering;
+use std::collections::{ BinaryHeap, HashMap, HashSet, VecDeque };
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+
+// Project libs
+
+/*
+How seen URLs are remembered.
+Exact keeps 64-bit fingerprint of every URL.
+Bloom uses fixed amount of memory sized for `capacity` URLs
+but may now and then wrongly claim URL was already seen.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeenMode {
+    Exact,
+    Bloom { capacity: usize, false_positive_rate: f64 },
+}
+
+impl Default for SeenMode {
+    fn default() -> SeenMode {
+        SeenMode::Exact
+    }
+}
+
+/*
+Order in which queued URLs are crawled
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrawlOrder {
+    BreadthFirst,
+    DepthFirst,
+    // Best scored first, see score()
+    Priority,
+}
+
+impl Default for CrawlOrder {
+    fn default() -> CrawlOrder {
+        CrawlOrder::BreadthFirst
+    }
+}
+
+/*
+64-bit hash of the (normalized) URL
+ */
+pub fn fingerprint(url: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    hasher.finish()
+}
+
+/*
+Plain Bloom filter working on URL fingerprints
+ */
+#[derive(Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: usize, false_positive_rate: f64) -> BloomFilter {
+        let n = if capacity > 0 { capacity as f64 } else { 1.0 };
+        let p = if false_positive_rate > 0.0 && false_positive_rate < 1.0 {
+            false_positive_rate
+        } else {
+            0.01
+        };
+        let ln2 = ::std::f64::consts::LN_2;
+
+        // Optimal size and number of hash functions
+        let num_bits = (-n * p.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().max(1.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; ((num_bits + 63) / 64) as usize],
+            num_bits: num_bits,
+            num_hashes: num_hashes,
+        }
+    }
+
+    /*
+    Bit positions derived from single fingerprint
+    using double hashing
+     */
+    fn positions(&self, fp: u64) -> Vec<u64> {
+        let h1 = fp;
+        let h2 = fp.rotate_left(32).wrapping_mul(0x9e3779b97f4a7c15) | 1;
+        (0..self.num_hashes as u64)
+            .map(|i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+            .collect()
+    }
+
+    pub fn contains(&self, fp: u64) -> bool {
+        self.positions(fp).iter()
+            .all(|&bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /*
+    Returns false if fingerprint (probably) was there already
+     */
+    pub fn insert(&mut self, fp: u64) -> bool {
+        let mut added = false;
+        for bit in self.positions(fp) {
+            let word = &mut self.bits[(bit / 64) as usize];
+            let mask = 1 << (bit % 64);
+            if *word & mask == 0 {
+                *word |= mask;
+                added = true;
+            }
+        }
+        added
+    }
+}
+
+#[derive(Debug)]
+enum SeenStore {
+    Exact(HashSet<u64>),
+    Bloom(BloomFilter),
+}
+
+/*
+Set of URLs with O(1) insert and lookup
+ */
+#[derive(Debug)]
+pub struct SeenSet {
+    store: SeenStore,
+    count: usize,
+}
+
+impl SeenSet {
+    pub fn new(mode: SeenMode) -> SeenSet {
+        let store = match mode {
+            SeenMode::Exact => SeenStore::Exact(HashSet::new()),
+            SeenMode::Bloom { capacity, false_positive_rate } => {
+                SeenStore::Bloom(BloomFilter::new(capacity, false_positive_rate))
+            },
+        };
+        SeenSet {
+            store: store,
+            count: 0,
+        }
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        let fp = fingerprint(url);
+        match self.store {
+            SeenStore::Exact(ref set) => set.contains(&fp),
+            SeenStore::Bloom(ref bloom) => bloom.contains(fp),
+        }
+    }
+
+    /*
+    Returns true if URL was not in the set before
+     */
+    pub fn insert(&mut self, url: &str) -> bool {
+        let fp = fingerprint(url);
+        let added = match self.store {
+            SeenStore::Exact(ref mut set) => set.insert(fp),
+            SeenStore::Bloom(ref mut bloom) => bloom.insert(fp),
+        };
+        if added {
+            self.count += 1;
+        }
+        added
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+}
+
+/*
+Info about URL waiting in the queue
+ */
+#[derive(Debug)]
+struct Pending {
+    depth: usize,
+    // From sitemap, 0.0 - 1.0
+    priority: Option<f32>,
+    // How many pages link to it so far
+    inlinks: u32,
+    score: i64,
+}
+
+/*
+Higher is better.
+Shallow pages, pages the site itself marks as important
+and pages many others link to go first.
+ */
+fn score(depth: usize, priority: Option<f32>, inlinks: u32) -> i64 {
+    let priority = priority.unwrap_or(0.5).max(0.0).min(1.0);
+    (priority * 100.0) as i64 + 10 * inlinks.min(100) as i64 - 20 * depth as i64
+}
+
+/*
+Heap item for priority order.
+Ties go to the URL queued first.
+ */
+#[derive(Debug, PartialEq, Eq)]
+struct Scored {
+    score: i64,
+    seq: u64,
+    url: String,
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        self.score.cmp(&other.score)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/*
+URL taken out of the frontier
+ */
+#[derive(Debug, PartialEq)]
+pub struct QueuedUrl {
+    pub url: String,
+    // Number of links followed from the seed
+    pub depth: usize,
+}
+
+/*
+Queue of URLs waiting to be crawled.
+URL that was queued once is never queued again.
+ */
+#[derive(Debug)]
+pub struct Frontier {
+    order: CrawlOrder,
+    queue: VecDeque<String>,
+    heap: BinaryHeap<Scored>,
+    pending: HashMap<String, Pending>,
+    queued: SeenSet,
+    seq: u64,
+}
+
+impl Frontier {
+    pub fn new(order: CrawlOrder, mode: SeenMode) -> Frontier {
+        Frontier {
+            order: order,
+            queue: VecDeque::new(),
+            heap: BinaryHeap::new(),
+            pending: HashMap::new(),
+            queued: SeenSet::new(mode),
+            seq: 0,
+        }
+    }
+
+    /*
+    Returns false if URL was queued before.
+    If its still waiting its inlink count and depth get updated.
+     */
+    pub fn push(&mut self, url: String, depth: usize, priority: Option<f32>) -> bool {
+        let rescored = match self.pending.get_mut(&url) {
+            Some(p) => {
+                p.inlinks += 1;
+                p.depth = p.depth.min(depth);
+                if priority.is_some() {
+                    p.priority = priority;
+                }
+                p.score = score(p.depth, p.priority, p.inlinks);
+                Some(p.score)
+            },
+            None => None,
+        };
+        if let Some(new_score) = rescored {
+            if self.order == CrawlOrder::Priority {
+                self.push_scored(url, new_score);
+            }
+            return false;
+        }
+
+        if !self.queued.insert(&url) {
+            return false;
+        }
+
+        let s = score(depth, priority, 0);
+        self.pending.insert(url.clone(), Pending {
+            depth: depth,
+            priority: priority,
+            inlinks: 0,
+            score: s,
+        });
+        match self.order {
+            CrawlOrder::Priority => self.push_scored(url, s),
+            _ => self.queue.push_back(url),
+        }
+        true
+    }
+
+    fn push_scored(&mut self, url: String, score: i64) {
+        self.seq += 1;
+        self.heap.push(Scored {
+            score: score,
+            seq: self.seq,
+            url: url,
+        });
+    }
+
+    pub fn pop(&mut self) -> Option<QueuedUrl> {
+        loop {
+            let url = match self.order {
+                CrawlOrder::BreadthFirst => self.queue.pop_front(),
+                CrawlOrder::DepthFirst => self.queue.pop_back(),
+                CrawlOrder::Priority => {
+                    match self.heap.pop() {
+                        // Rescored URLs leave stale copies in the heap
+                        Some(item) => match self.pending.get(&item.url) {
+                            Some(p) if p.score == item.score => Some(item.url),
+                            _ => continue,
+                        },
+                        None => None,
+                    }
+                },
+            };
+
+            return url.map(|url| {
+                let depth = self.pending.remove(&url).map(|p| p.depth).unwrap_or(0);
+                QueuedUrl {
+                    url: url,
+                    depth: depth,
+                }
+            });
+        }
+    }
+
+    /*
+    True if URL was ever queued, popped ones included
+     */
+    pub fn contains(&self, url: &str) -> bool {
+        self.queued.contains(url)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_seen_set() {
+    for mode in [SeenMode::Exact,
+                 SeenMode::Bloom { capacity: 1000, false_positive_rate: 0.001 }].iter() {
+        let mut seen = SeenSet::new(*mode);
+        assert!(seen.insert("http://example.com/a"));
+        assert!(!seen.insert("http://example.com/a"));
+        assert!(seen.insert("http://example.com/b"));
+        assert!(seen.contains("http://example.com/a"));
+        assert!(!seen.contains("http://example.com/c"));
+        assert_eq!(seen.len(), 2);
+    }
+}
+
+#[test]
+fn test_bloom_false_positives() {
+    let mut bloom = BloomFilter::new(10000, 0.01);
+    for i in 0..10000 {
+        bloom.insert(fingerprint(&format!("http://example.com/{}", i)));
+    }
+    for i in 0..10000 {
+        assert!(bloom.contains(fingerprint(&format!("http://example.com/{}", i))));
+    }
+    let false_positives = (0..10000)
+        .filter(|i| bloom.contains(fingerprint(&format!("http://example.com/x{}", i))))
+        .count();
+    // 1% expected, give it some slack
+    assert!(false_positives < 300);
+}
+
+#[test]
+fn test_frontier() {
+    let mut frontier = Frontier::new(CrawlOrder::DepthFirst, SeenMode::Exact);
+    assert!(frontier.push("http://example.com/a".to_owned(), 0, None));
+    assert!(frontier.push("http://example.com/b".to_owned(), 1, None));
+    assert!(!frontier.push("http://example.com/a".to_owned(), 1, None));
+    assert_eq!(frontier.len(), 2);
+
+    assert_eq!(frontier.pop(), Some(QueuedUrl { url: "http://example.com/b".to_owned(), depth: 1 }));
+    assert_eq!(frontier.pop(), Some(QueuedUrl { url: "http://example.com/a".to_owned(), depth: 0 }));
+    assert!(frontier.is_empty());
+
+    // Once popped it still wont be queued again
+    assert!(!frontier.push("http://example.com/a".to_owned(), 0, None));
+    assert_eq!(frontier.pop(), None);
+}
+
+#[test]
+fn test_breadth_first() {
+    let mut frontier = Frontier::new(CrawlOrder::BreadthFirst, SeenMode::Exact);
+    frontier.push("http://example.com/".to_owned(), 0, None);
+    frontier.push("http://example.com/a".to_owned(), 1, None);
+    frontier.push("http://example.com/b".to_owned(), 1, None);
+
+    let order: Vec<String> = (0..3).filter_map(|_| frontier.pop()).map(|q| q.url).collect();
+    assert_eq!(order, vec!["http://example.com/", "http://example.com/a", "http://example.com/b"]);
+}
+
+#[test]
+fn test_priority() {
+    let mut frontier = Frontier::new(CrawlOrder::Priority, SeenMode::Exact);
+    frontier.push("http://example.com/deep".to_owned(), 5, None);
+    frontier.push("http://example.com/low".to_owned(), 1, Some(0.1));
+    frontier.push("http://example.com/plain".to_owned(), 1, None);
+    frontier.push("http://example.com/important".to_owned(), 1, Some(1.0));
+    frontier.push("http://example.com/popular".to_owned(), 2, None);
+    // Linked from many pages
+    for _ in 0..10 {
+        frontier.push("http://example.com/popular".to_owned(), 2, None);
+    }
+
+    let order: Vec<String> = (0..6).filter_map(|_| frontier.pop()).map(|q| q.url).collect();
+    assert_eq!(order, vec!["http://example.com/popular",
+                           "http://example.com/important",
+                           "http://example.com/plain",
+                           "http://example.com/low",
+                           "http://example.com/deep"]);
+    assert!(frontier.is_empty());
+}
@@ -0,0 +1,288 @@
+// Crates
+
+// Modules
+
+// Standard lib
+
+// Project libs
+
+/*
+Opening html tag along with its attributes.
+Names are lowercased, values have entities decoded.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v.as_str())
+    }
+}
+
+/*
+Link found on the page, not resolved yet
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    pub href: String,
+    pub nofollow: bool,
+}
+
+/*
+Links of the page along with <base href> if any
+ */
+#[derive(Debug, PartialEq)]
+pub struct Links {
+    pub base: Option<String>,
+    pub links: Vec<Link>,
+}
+
+/*
+Returns all opening tags of the document.
+Comments, doctype and content of <script> and <style>
+are skipped so URLs in there are never picked up.
+ */
+pub fn tags(html: &str) -> Vec<Tag> {
+    // Only ASCII is lowercased so byte offsets stay the same
+    let lower = html.to_ascii_lowercase();
+    let bytes = html.as_bytes();
+    let mut tags = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        let rest = &lower[start..];
+
+        if rest.starts_with("<!--") {
+            pos = match lower[start + 4..].find("-->") {
+                Some(end) => start + 4 + end + 3,
+                None => break,
+            };
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") || rest.starts_with("</") {
+            pos = match lower[start..].find('>') {
+                Some(end) => start + end + 1,
+                None => break,
+            };
+            continue;
+        }
+        match bytes.get(start + 1) {
+            Some(c) if (*c as char).is_ascii_alphabetic() => {},
+            _ => {
+                // Just a "<" in text
+                pos = start + 1;
+                continue;
+            },
+        }
+
+        let (tag, end) = parse_tag(html, &lower, start + 1);
+        pos = end;
+
+        // Raw text elements, skip till closing tag
+        if tag.name == "script" || tag.name == "style" {
+            let closing = format!("</{}", tag.name);
+            pos = match lower[pos..].find(&closing) {
+                Some(idx) => pos + idx,
+                None => html.len(),
+            };
+        }
+        tags.push(tag);
+    }
+    tags
+}
+
+/*
+Parses tag starting right after "<".
+Returns the tag and position after its ">".
+ */
+fn parse_tag(html: &str, lower: &str, start: usize) -> (Tag, usize) {
+    let bytes = html.as_bytes();
+    let len = bytes.len();
+    let is_name_end = |c: u8| c.is_ascii_whitespace() || c == b'/' || c == b'>' || c == b'=';
+
+    let mut pos = start;
+    while pos < len && !is_name_end(bytes[pos]) {
+        pos += 1;
+    }
+    let mut tag = Tag {
+        name: lower[start..pos].to_owned(),
+        attributes: Vec::new(),
+    };
+
+    loop {
+        while pos < len && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'/') {
+            pos += 1;
+        }
+        if pos >= len {
+            return (tag, len);
+        }
+        if bytes[pos] == b'>' {
+            return (tag, pos + 1);
+        }
+
+        // Attribute name
+        let name_start = pos;
+        while pos < len && !is_name_end(bytes[pos]) {
+            pos += 1;
+        }
+        // Stray "=" without name
+        if pos == name_start {
+            pos += 1;
+            continue;
+        }
+        let name = lower[name_start..pos].to_owned();
+
+        while pos < len && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos >= len || bytes[pos] != b'=' {
+            tag.attributes.push((name, String::new()));
+            continue;
+        }
+        pos += 1;
+        while pos < len && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+
+        // Attribute value, quoted or not
+        let value = if pos < len && (bytes[pos] == b'"' || bytes[pos] == b'\'') {
+            let quote = bytes[pos] as char;
+            let value_start = pos + 1;
+            match html[value_start..].find(quote) {
+                Some(end) => {
+                    pos = value_start + end + 1;
+                    &html[value_start..value_start + end]
+                },
+                None => {
+                    pos = len;
+                    &html[value_start..]
+                },
+            }
+        } else {
+            let value_start = pos;
+            while pos < len && !bytes[pos].is_ascii_whitespace() && bytes[pos] != b'>' {
+                pos += 1;
+            }
+            &html[value_start..pos]
+        };
+        tag.attributes.push((name, decode_entities(value)));
+    }
+}
+
+/*
+Decodes the few entities that show up in URLs
+ */
+pub fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/*
+Collects a[href], area[href], link[href] and
+frame/iframe[src] links and the <base href> of the page
+ */
+pub fn extract_links(html: &str) -> Links {
+    let mut links = Links {
+        base: None,
+        links: Vec::new(),
+    };
+
+    for tag in tags(html) {
+        let attribute = match tag.name.as_str() {
+            "a" | "area" | "link" => "href",
+            "frame" | "iframe" => "src",
+            "base" => {
+                // Only the first <base> counts
+                if links.base.is_none() {
+                    links.base = tag.attr("href").map(|h| h.trim().to_owned());
+                }
+                continue;
+            },
+            _ => continue,
+        };
+
+        let href = match tag.attr(attribute) {
+            Some(h) if !h.trim().is_empty() => h.trim().to_owned(),
+            _ => continue,
+        };
+        let nofollow = tag.attr("rel")
+            .map(|rel| rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("nofollow")))
+            .unwrap_or(false);
+
+        links.links.push(Link {
+            href: href,
+            nofollow: nofollow,
+        });
+    }
+    links
+}
+
+#[cfg(test)]
+#[test]
+fn test_tags() {
+    let html = "<!DOCTYPE html><HTML lang=en><head>\
+                <!-- <a href='/commented'> -->\
+                <script>var s = '<a href=\"/script\">';</script>\
+                <STYLE>a > b { }</STYLE>\
+                </head><body>1 < 2 \
+                <a class=\"x\" HREF = '/one?a=1&amp;b=2' data-x>one</a>\
+                <img src=/img.png/>\
+                </body></html>";
+    let tags = tags(html);
+    let names: Vec<&str> = tags.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["html", "head", "script", "style", "body", "a", "img"]);
+
+    assert_eq!(tags[0].attr("lang"), Some("en"));
+    assert_eq!(tags[5].attr("href"), Some("/one?a=1&b=2"));
+    assert_eq!(tags[5].attr("class"), Some("x"));
+    assert_eq!(tags[5].attr("data-x"), Some(""));
+    assert_eq!(tags[6].attr("src"), Some("/img.png/"));
+}
+
+#[test]
+fn test_extract_links() {
+    let html = "<head><base href=\"http://example.com/dir/\">\
+                <link rel=canonical href=\"/canonical\"></head>\
+                <body><a href='page2.html'>2</a>\
+                <a rel=\"nofollow\" href=\"../x\">x</a>\
+                <a rel='external NoFollow' href=\"/y\">y</a>\
+                <a name='anchor'>no href</a>\
+                <map><area href=\"/area\"></map>\
+                <iframe src=\"http://other.com/frame\"></iframe>\
+                <a href='  '>empty</a></body>";
+    let links = extract_links(html);
+
+    assert_eq!(links.base, Some("http://example.com/dir/".to_owned()));
+    let found: Vec<(&str, bool)> = links.links.iter()
+        .map(|l| (l.href.as_str(), l.nofollow))
+        .collect();
+    assert_eq!(found, vec![("/canonical", false),
+                           ("page2.html", false),
+                           ("../x", true),
+                           ("/y", true),
+                           ("/area", false),
+                           ("http://other.com/frame", false)]);
+}
+
+#[test]
+fn test_broken_html() {
+    assert_eq!(tags("<a href='unterminated"),
+               vec![Tag { name: "a".to_owned(),
+                          attributes: vec![("href".to_owned(), "unterminated".to_owned())] }]);
+    assert_eq!(tags("<a href=").len(), 1);
+    assert_eq!(tags("<!-- never closed <a href='/x'>").len(), 0);
+    assert_eq!(tags("<script><a href='/x'>").len(), 1);
+}
@@ -0,0 +1,88 @@
+// Crates
+extern crate curl;
+
+// Modules
+
+// Standard lib
+use std::time::Duration;
+use self::curl::easy::Easy;
+
+// Project libs
+use domain::USER_AGENT;
+
+// Where site owners can find out who we are and how to block us
+pub const CONTACT_URL: &'static str = "http://catdamnit.com/mokosza";
+
+// RFC 9309 asks to follow at least five redirects
+const MAX_REDIRECTS: u32 = 5;
+
+/*
+Settings of every request we make, robots.txt,
+sitemaps and pages alike
+ */
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    // Should contain USER_AGENT so robots.txt rules make sense
+    pub user_agent: String,
+    pub connect_timeout: Duration,
+    // Whole transfer including the download
+    pub timeout: Duration,
+    pub max_redirects: u32,
+    // Ask for gzip/deflate/brotli, curl decodes it for us
+    pub compression: bool,
+    // Downloads bigger than this are aborted, None means no limit
+    pub max_body_size: Option<usize>,
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> HttpClientConfig {
+        HttpClientConfig {
+            user_agent: format!("{}/{} (+{})",
+                                USER_AGENT, env!("CARGO_PKG_VERSION"), CONTACT_URL),
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(60),
+            max_redirects: MAX_REDIRECTS,
+            compression: true,
+            max_body_size: Some(10 * 1024 * 1024),
+        }
+    }
+}
+
+impl HttpClientConfig {
+    /*
+    Sets up the handle according to the config
+     */
+    pub fn configure(&self, easy: &mut Easy) -> Result<(), curl::Error> {
+        try!(easy.useragent(&self.user_agent));
+        try!(easy.connect_timeout(self.connect_timeout));
+        try!(easy.timeout(self.timeout));
+        try!(easy.follow_location(self.max_redirects > 0));
+        try!(easy.max_redirections(self.max_redirects));
+        if self.compression {
+            // Empty string means all encodings curl was built with
+            try!(easy.accept_encoding(""));
+        }
+        Ok(())
+    }
+
+    /*
+    True if body of that size should not be downloaded
+     */
+    pub fn too_large(&self, size: usize) -> bool {
+        self.max_body_size.map_or(false, |max| size > max)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_http_client_config() {
+    let config = HttpClientConfig::default();
+
+    assert!(config.user_agent.starts_with("mokosza/"));
+    assert!(config.user_agent.contains(CONTACT_URL));
+    assert!(!config.too_large(1024));
+    assert!(config.too_large(20 * 1024 * 1024));
+
+    let unlimited = HttpClientConfig { max_body_size: None, .. HttpClientConfig::default() };
+    assert!(!unlimited.too_large(::std::usize::MAX));
+}
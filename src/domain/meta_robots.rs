@@ -0,0 +1,137 @@
+// Crates
+
+// Modules
+
+// Standard lib
+
+// Project libs
+use domain::html::tags;
+
+/*
+Per page robots directives coming from
+<meta name="robots"> tags and X-Robots-Tag headers
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RobotsDirectives {
+    pub noindex: bool,
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+
+    /*
+    Directives are cumulative, the most restrictive ones win
+     */
+    pub fn merge(&mut self, other: RobotsDirectives) {
+        self.noindex |= other.noindex;
+        self.nofollow |= other.nofollow;
+    }
+
+    fn apply(&mut self, directive: &str) {
+        match directive.trim().to_lowercase().as_str() {
+            "noindex" => self.noindex = true,
+            "nofollow" => self.nofollow = true,
+            "none" => {
+                self.noindex = true;
+                self.nofollow = true;
+            },
+            _ => {},
+        }
+    }
+
+    /*
+    X-Robots-Tag: noindex, nofollow
+    X-Robots-Tag: mokosza: noindex
+    Directives prefixed with other user-agent are ignored.
+     */
+    pub fn from_header(value: &str, user_agent: &str) -> RobotsDirectives {
+        let agent = user_agent.to_lowercase();
+        let mut directives = RobotsDirectives::default();
+        let mut applies = true;
+
+        for token in value.split(',') {
+            let token = token.trim();
+            let directive = match token.find(':') {
+                Some(idx) => {
+                    let prefix = token[..idx].trim().to_lowercase();
+                    if prefix == "unavailable_after" {
+                        continue;
+                    }
+                    // Following directives are meant for this agent
+                    applies = prefix == agent;
+                    &token[idx + 1..]
+                },
+                None => token,
+            };
+            if applies {
+                directives.apply(directive);
+            }
+        }
+        directives
+    }
+
+    /*
+    Collects directives from all robots meta tags meant for us
+     */
+    pub fn from_html(html: &str, user_agent: &str) -> RobotsDirectives {
+        let agent = user_agent.to_lowercase();
+        let mut directives = RobotsDirectives::default();
+
+        for tag in tags(html) {
+            if tag.name != "meta" {
+                continue;
+            }
+            let name = match tag.attr("name") {
+                Some(n) => n.trim().to_lowercase(),
+                None => continue,
+            };
+            if name != "robots" && name != agent {
+                continue;
+            }
+            if let Some(content) = tag.attr("content") {
+                for directive in content.split(',') {
+                    directives.apply(directive);
+                }
+            }
+        }
+        directives
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_from_header() {
+    let d = RobotsDirectives::from_header("noindex, nofollow", "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: true, nofollow: true });
+
+    let d = RobotsDirectives::from_header("none", "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: true, nofollow: true });
+
+    let d = RobotsDirectives::from_header("googlebot: noindex, nofollow", "mokosza");
+    assert_eq!(d, RobotsDirectives::default());
+
+    let d = RobotsDirectives::from_header("Mokosza: noindex", "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: true, nofollow: false });
+
+    let d = RobotsDirectives::from_header("unavailable_after: 25 Jun 2010 15:00:00 PST, nofollow",
+                                          "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: false, nofollow: true });
+}
+
+#[test]
+fn test_from_html() {
+    let html = "<html><head>\
+                <meta charset='utf-8'>\
+                <META NAME=\"ROBOTS\" CONTENT=\"NOINDEX\">\
+                <meta name=\"googlebot\" content=\"nofollow\">\
+                </head><body></body></html>";
+    let d = RobotsDirectives::from_html(html, "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: true, nofollow: false });
+
+    let html = "<meta content='nofollow' name='mokosza' />";
+    let mut d = RobotsDirectives::from_html(html, "mokosza");
+    assert_eq!(d, RobotsDirectives { noindex: false, nofollow: true });
+
+    d.merge(RobotsDirectives::from_header("noindex", "mokosza"));
+    assert_eq!(d, RobotsDirectives { noindex: true, nofollow: true });
+}
@@ -1,45 +1,225 @@
 // Crates
 extern crate curl;
+extern crate time;
 
 // extern libs
-use self::curl::easy::Easy;
+use self::curl::easy::{ Easy, List };
 
 // Modules
 pub mod domain_url;
 pub mod domain_error;
+pub mod robots;
+pub mod sitemap;
+pub mod robots_cache;
+pub mod meta_robots;
+pub mod html;
+pub mod frontier;
+pub mod charset;
+pub mod http_client;
+pub mod content_filter;
+pub mod page_store;
+pub mod discovered;
 
 // stdlib
 use std::fmt;
-use std::ops::Deref;
 use std::error::Error;
+use std::time::{ Duration, Instant };
+use std::cell::Cell;
+use std::thread;
+use std::collections::HashMap;
 
 use domain::domain_error::DomainError;
+use domain::robots::Robots;
+use domain::robots_cache::{ RobotsCache, CachedRobots };
+use domain::sitemap::{ Sitemap, SitemapEntry };
+use domain::meta_robots::RobotsDirectives;
+use domain::domain_url::{ normalize_url, NormalizeOptions, TrapDetector, TrapOptions };
+use domain::http_client::HttpClientConfig;
+use domain::content_filter::ContentFilter;
+use domain::page_store::PageRecord;
+use domain::frontier::{ Frontier, SeenSet, SeenMode, CrawlOrder, QueuedUrl };
 use log::*;
 
+// Name we introduce ourselves with in robots.txt
+pub const USER_AGENT: &'static str = "mokosza";
+
+// How deep to follow sitemap index files
+const MAX_SITEMAP_DEPTH: usize = 3;
+// Upper limit of sitemap files fetched per domain
+const MAX_SITEMAPS: usize = 50;
+
 /* 
 Page struct definition
  */
-pub struct Page(String);
+pub struct Page {
+    // URL we asked for
+    pub url: String,
+    // URL after following redirects
+    pub final_url: String,
+    pub status: u32,
+    // Headers of the final response
+    pub headers: Vec<(String, String)>,
+    pub content_type: Option<String>,
+    // Unix timestamp of when the download started
+    pub fetched_at: i64,
+    pub duration: Duration,
+    // Size of the body in bytes
+    pub size: usize,
+    pub body: Vec<u8>,
+    // Body decoded to UTF-8 text
+    text: String,
+    // Encoding the body was decoded from
+    pub encoding: &'static str,
+    // What the page allows us to do with it
+    pub robots: RobotsDirectives,
+}
+
+impl Page {
+    /*
+    Page downloaded just now from the URL without redirects
+     */
+    pub fn new(url: &str, status: u32, headers: Vec<(String, String)>,
+               body: Vec<u8>) -> Page {
+        let content_type = header_values(&headers, "Content-Type")
+            .first()
+            .map(|v| v.to_string());
+        let decoded = charset::decode(&body, content_type.as_ref().map(|c| c.as_str()));
+
+        Page {
+            url: url.to_owned(),
+            final_url: url.to_owned(),
+            status: status,
+            headers: headers,
+            content_type: content_type,
+            fetched_at: time::get_time().sec,
+            duration: Duration::from_secs(0),
+            size: body.len(),
+            body: body,
+            text: decoded.text,
+            encoding: decoded.encoding,
+            robots: RobotsDirectives::default(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /*
+    All values of the header, name is case insensitive
+     */
+    pub fn header(&self, name: &str) -> Vec<&str> {
+        header_values(&self.headers, name)
+    }
+
+    /*
+    Server said the page did not change since the last crawl
+     */
+    pub fn is_not_modified(&self) -> bool {
+        self.status == 304
+    }
+
+    /*
+    False if page asked not to be indexed/stored
+     */
+    pub fn may_index(&self) -> bool {
+        !self.robots.noindex
+    }
+
+    /*
+    False if links on the page should not be followed
+     */
+    pub fn may_follow(&self) -> bool {
+        !self.robots.nofollow
+    }
+}
 
 impl fmt::Display for Page {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.text)
     }
 }
 
-impl Deref for Page {
-    type Target = String;
+/*
+Raw HTTP response
+ */
+struct Response {
+    code: u32,
+    // Where we ended up after redirects
+    url: String,
+    fetched_at: i64,
+    duration: Duration,
+    // Headers of the final response after redirects
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
 
-    fn deref(&self) -> &String {
-        &self.0
+impl Response {
+    /*
+    All values of the header, name is case insensitive
+     */
+    fn header(&self, name: &str) -> Vec<&str> {
+        header_values(&self.headers, name)
+    }
+}
+
+/*
+Per crawl settings of the domain
+ */
+#[derive(Debug, Clone)]
+pub struct DomainOptions {
+    pub normalize: NormalizeOptions,
+    // Exact or memory bounded dedup of URLs
+    pub seen: SeenMode,
+    pub order: CrawlOrder,
+    // Links deeper than this from the seed are not followed
+    pub max_depth: Option<usize>,
+    pub traps: TrapOptions,
+    pub http: HttpClientConfig,
+    pub content: ContentFilter,
+}
+
+impl Default for DomainOptions {
+    fn default() -> DomainOptions {
+        DomainOptions {
+            normalize: NormalizeOptions::default(),
+            seen: SeenMode::default(),
+            order: CrawlOrder::default(),
+            max_depth: Some(20),
+            traps: TrapOptions::default(),
+            http: HttpClientConfig::default(),
+            content: ContentFilter::default(),
+        }
     }
 }
 
 pub struct Domain<'a> {
     pub domain: &'a str,
-    pub paths_visited: Vec<String>,
-    pub paths_to_visit: Vec<String>,
-    robots: Vec<String>,
+    pub paths_visited: SeenSet,
+    pub paths_to_visit: Frontier,
+    // Extra info about the URLs found in sitemaps
+    pub sitemap_entries: HashMap<String, SitemapEntry>,
+    // Depth of the page we are currently crawling
+    current_depth: usize,
+    // How URLs are canonicalized before deduplication
+    pub normalize_options: NormalizeOptions,
+    max_depth: Option<usize>,
+    traps: TrapDetector,
+    // Links rejected as crawler traps, each counted once
+    trapped: SeenSet,
+    pub traps_rejected: usize,
+    // Links deeper than max_depth, each counted once
+    truncated: SeenSet,
+    pub depth_truncated: usize,
+    robots: Robots,
+    http: HttpClientConfig,
+    // What kind of URLs and responses we want
+    pub content_filter: ContentFilter,
+    // Pause between requests made back to back i.e. HEAD and GET,
+    // the crawler keeps it in line with the host politeness
+    pub delay: Duration,
+    // Server failed to give us robots.txt so we cant crawl now
+    robots_unavailable: bool,
 }
 
 impl<'a> PartialEq for Domain<'a> {
@@ -50,62 +230,183 @@ impl<'a> PartialEq for Domain<'a> {
 
 impl<'a> Domain<'a> {
     pub fn new(domain_url: &str) -> Domain {
+        Domain::init(domain_url, &DomainOptions::default(), None)
+    }
+
+    /*
+    Same as new() but with given options and robots.txt
+    taken from the cache if possible so it is not
+    downloaded over and over
+     */
+    pub fn with_options(domain_url: &'a str, options: &DomainOptions,
+                        cache: &RobotsCache) -> Domain<'a> {
+        Domain::init(domain_url, options, Some(cache))
+    }
+
+    fn init(domain_url: &'a str, options: &DomainOptions,
+            cache: Option<&RobotsCache>) -> Domain<'a> {
  
         let mut dom = Domain {
             domain: domain_url,
-            robots: Vec::new(),
-            paths_visited: Vec::new(),
-            paths_to_visit: Vec::new(),
+            robots: Robots::allow_all(),
+            paths_visited: SeenSet::new(options.seen),
+            paths_to_visit: Frontier::new(options.order, options.seen),
+            sitemap_entries: HashMap::new(),
+            current_depth: 0,
+            normalize_options: options.normalize,
+            max_depth: options.max_depth,
+            traps: TrapDetector::new(options.traps),
+            trapped: SeenSet::new(options.seen),
+            traps_rejected: 0,
+            truncated: SeenSet::new(options.seen),
+            depth_truncated: 0,
+            http: options.http.clone(),
+            content_filter: options.content.clone(),
+            delay: Duration::from_secs(0),
+            robots_unavailable: false,
         };
 
         // Add the actual domin URL to list
-        dom.add_to_visit(domain_url);
-
-        let _ = dom.check_robots()
-            .map_err(|err| log_err(&format!("Error: {}", err.description())))
-            .and_then(|()| {
-                log_info(&format!("Succesfully fetched robots.txt from: {} ", domain_url));
-                log_info(&format!("We have {} disallowed paths", dom.robots.len()));
-                Ok(())
-            });
+        let root = dom.normalize(domain_url);
+        dom.paths_to_visit.push(root, 0, None);
+
+        let robots_result = dom.check_robots(cache);
+        if let Err(err) = robots_result {
+            // Until we know the rules assume everything is forbidden
+            log_err(&format!("Error: {}", err.description()));
+            dom.robots = Robots::disallow_all();
+            dom.robots_unavailable = true;
+            return dom;
+        }
+        log_info(&format!("Succesfully fetched robots.txt from: {} ", domain_url));
+        log_info(&format!("We have {} rules for {}", dom.robots.len(), USER_AGENT));
         dom
     }
 
     pub fn get_webpage(&self, url: &str) -> Result<Page, DomainError> {
+        self.get_webpage_since(url, None)
+    }
+
+    /*
+    Same as get_webpage() but asks only for changes since the
+    previous crawl. Unchanged page comes back with status 304
+    and empty body.
+     */
+    pub fn get_webpage_since(&self, url: &str, previous: Option<&PageRecord>)
+                             -> Result<Page, DomainError> {
         if self.is_url_in_robots(url) {
             log_warn(&format!("{} skipped. Forbidden by robots.txt", url));
             return Err(DomainError::SkippedURL);
         }
 
-        match self.page_curl(url) {
+        match self.page_curl(url, previous) {
             Ok(page) => Ok(page),
             Err(err) => Err(err),
         }
     }
 
+    /*
+    Canonical form of the URL used for deduplication.
+    URLs we cannot make sense of are kept as they are.
+     */
+    pub fn normalize(&self, url: &str) -> String {
+        normalize_url(url, &self.normalize_options).unwrap_or(url.to_owned())
+    }
+
     pub fn add_visited(&mut self, url: &str) -> usize {
-        self.paths_visited.push(url.to_owned());
+        let s = self.normalize(url);
+        self.paths_visited.insert(&s);
         self.paths_visited.len()
     }
 
+    /*
+    Queues URL found on the page we are crawling now
+     */
     pub fn add_to_visit(&mut self, url: &str) -> usize {
+        let depth = self.current_depth + 1;
+        let s = self.normalize(url);
+
+        // Known links were scored already when first found
+        if self.trapped.contains(&s) {
+            return self.paths_to_visit.len();
+        }
+        if self.paths_visited.contains(&s) || self.paths_to_visit.contains(&s) {
+            return self.queue_url(&s, depth, None);
+        }
+
+        // Suspicious links go to the back of the (priority) queue
+        let score = self.traps.score(&s);
+        if self.traps.is_trap(score) {
+            log_warn(&format!("URL: {} looks like crawler trap...skipping", s));
+            self.trapped.insert(&s);
+            self.traps_rejected += 1;
+            return self.paths_to_visit.len();
+        }
+        let priority = if score > 0 { Some(0.0) } else { None };
+
+        self.queue_url(&s, depth, priority)
+    }
+
+    fn queue_url(&mut self, url: &str, depth: usize, priority: Option<f32>) -> usize {
+        if self.max_depth.map_or(false, |max| depth > max) {
+            // Known by other, shorter path is not lost
+            let known = self.paths_visited.contains(url) || self.paths_to_visit.contains(url);
+            if !known && self.truncated.insert(url) {
+                self.depth_truncated += 1;
+            }
+            return self.paths_to_visit.len();
+        }
+        // Skip .jpg, .pdf, .zip etc.
+        if !self.content_filter.allows_url(url) {
+            log_warn(&format!("URL: {} ends with denied extension...skipping", url));
+            return self.paths_to_visit.len();
+        }
         // We dont want any duplicates here
-        let s = url.to_owned();
-        // Add it if not already in list and
+        let s = self.normalize(url);
+        // Add it if never queued before and
         // if was not visited before
-        if !self.paths_to_visit.contains(&s)
-            && !self.paths_visited.contains(&s) {
-            self.paths_to_visit.push(s);
+        if !self.paths_visited.contains(&s) {
+            self.paths_to_visit.push(s, depth, priority);
         }
         self.paths_to_visit.len()
     }
 
+    /*
+    Takes next URL to crawl out of the frontier.
+    Links found from now on are one level deeper than it.
+     */
+    pub fn next_url(&mut self) -> Option<QueuedUrl> {
+        let next = self.paths_to_visit.pop();
+        if let Some(ref q) = next {
+            self.current_depth = q.depth;
+        }
+        next
+    }
+
+    /*
+    Delay between requests the host asked for in robots.txt
+     */
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.robots.crawl_delay()
+    }
+
+    /*
+    Waits between requests Domain makes back to back
+     */
+    fn pause(&self) {
+        thread::sleep(self.delay);
+    }
+
+    /*
+    True when robots.txt could not be fetched due to server
+    or network error. Domain should be crawled again later.
+     */
+    pub fn robots_unavailable(&self) -> bool {
+        self.robots_unavailable
+    }
+
     fn is_url_in_robots(&self, url: &str) -> bool {
-        let isit = &self.robots.clone().into_iter()
-            .filter(|path| url.contains(path))
-            .collect::<String>();
-        
-        return isit.len() > 0;
+        !self.robots.is_allowed(url)
     }
 
     fn get_robots_url(&self) -> String {
@@ -115,69 +416,361 @@ impl<'a> Domain<'a> {
         robots_url
     }
 
-    fn page_curl(&self, url: &str) -> Result<Page, DomainError> {
+    /*
+    Checks if URL points to the domain we are crawling
+     */
+    fn is_own_url(&self, url: &str) -> bool {
+        url.starts_with(self.domain)
+            && match url[self.domain.len()..].chars().next() {
+                None | Some('/') | Some('?') | Some('#') => true,
+                _ => false,
+            }
+    }
+
+    fn page_curl(&self, url: &str, previous: Option<&PageRecord>) -> Result<Page, DomainError> {
+        let filter = Some(&self.content_filter);
+        if self.content_filter.head_first {
+            // Only to learn the type and size, status comes with GET
+            try!(self.request(url, filter, true, &[]));
+            // GET is second request to the same server
+            self.pause();
+        }
+        let conditions = previous.map_or(Vec::new(), |p| p.conditional_headers());
+        let resp = try!(self.request(url, filter, false, &conditions));
+        if resp.code >= 400 {
+            let retry_after = resp.header("Retry-After")
+                .last()
+                .and_then(|value| parse_retry_after(value));
+            return Err(DomainError::HttpStatus(resp.code, retry_after));
+        }
+
+        let mut robots = RobotsDirectives::default();
+        for value in resp.header("X-Robots-Tag") {
+            robots.merge(RobotsDirectives::from_header(value, USER_AGENT));
+        }
+
+        let mut page = Page::new(url, resp.code, resp.headers, resp.body);
+        page.final_url = resp.url;
+        page.fetched_at = resp.fetched_at;
+        page.duration = resp.duration;
+
+        robots.merge(RobotsDirectives::from_html(page.text(), USER_AGENT));
+        if robots.noindex || robots.nofollow {
+            log_info(&format!("{} has robots directives {:?}", url, robots));
+        }
+        page.robots = robots;
+
+        Ok(page)
+    }
+
+    /*
+    Downloads the URL following redirects.
+    Returns HTTP status code and headers along with the content.
+     */
+    fn fetch(&self, url: &str) -> Result<Response, DomainError> {
+        self.request(url, None, false, &[])
+    }
+
+    /*
+    Same as fetch() but aborts once the headers show
+    successful response of type the filter does not allow.
+    With head set only HEAD request is made.
+    Extra headers are sent along i.e. If-None-Match.
+     */
+    fn request(&self, url: &str, filter: Option<&ContentFilter>, head: bool,
+               extra_headers: &[String]) -> Result<Response, DomainError> {
+        let fetched_at = time::get_time().sec;
         let mut easy = Easy::new();
         let mut dst = Vec::new();
+        let mut headers = Vec::new();
+        let mut status = 0;
+        let too_large = Cell::new(false);
+        let unwanted = Cell::new(false);
         {
+            let http = &self.http;
             try!(easy.url(url).map_err(DomainError::FetchError));
+            try!(http.configure(&mut easy).map_err(DomainError::FetchError));
+            try!(easy.nobody(head).map_err(DomainError::FetchError));
+            if !extra_headers.is_empty() {
+                let mut list = List::new();
+                for header in extra_headers {
+                    try!(list.append(header).map_err(DomainError::FetchError));
+                }
+                try!(easy.http_headers(list).map_err(DomainError::FetchError));
+            }
             
             let mut transfer = easy.transfer();
             try!(transfer.write_function(|data| {
+                if http.too_large(dst.len() + data.len()) {
+                    too_large.set(true);
+                    // Taking less than we were given aborts the transfer
+                    return Ok(0);
+                }
                 dst.extend_from_slice(data);
                 Ok(data.len())
             }).map_err(DomainError::FetchError));
+            try!(transfer.header_function(|line| {
+                if line.starts_with(b"HTTP/") {
+                    status = status_code(line);
+                }
+                parse_header_line(line, &mut headers);
+                // No point downloading what we would throw away
+                let announced = header_values(&headers, "Content-Length")
+                    .last()
+                    .and_then(|v| v.parse::<usize>().ok());
+                if announced.map_or(false, |size| http.too_large(size)) {
+                    too_large.set(true);
+                    return false;
+                }
+                // Blank line ends the headers, redirects and errors are not checked
+                let headers_done = line == b"\r\n" || line == b"\n";
+                if headers_done && status >= 200 && status < 300 {
+                    let content_type = header_values(&headers, "Content-Type");
+                    if filter.map_or(false, |f| !f.allows_type(content_type.last().cloned())) {
+                        unwanted.set(true);
+                        return false;
+                    }
+                }
+                true
+            }).map_err(DomainError::FetchError));
             
-            try!(transfer.perform().map_err(DomainError::FetchError));
+            let performed = transfer.perform();
+            if too_large.get() {
+                log_warn(&format!("{} is bigger than {:?} bytes...aborted",
+                                  url, http.max_body_size));
+                return Err(DomainError::BodyTooLarge);
+            }
+            if !unwanted.get() {
+                try!(performed.map_err(DomainError::FetchError));
+            }
         }
-        /* Its unsafe because sometimes the captured data from webpage 
-        is not correctly utf-8 formrmated
-        I can live with a odd letter malformed here and there
-         */
-        unsafe {
-            return Ok(Page(String::from_utf8_unchecked(dst)))
+        if unwanted.get() {
+            let content_type = header_values(&headers, "Content-Type")
+                .last()
+                .map_or(String::new(), |ct| ct.to_string());
+            log_info(&format!("{} is {}...aborted", url, content_type));
+            return Err(DomainError::UnwantedContent(content_type));
         }
+        let code = try!(easy.response_code().map_err(DomainError::FetchError));
+        let duration = try!(easy.total_time().map_err(DomainError::FetchError));
+        let final_url = match try!(easy.effective_url().map_err(DomainError::FetchError)) {
+            Some(u) => u.to_owned(),
+            None => url.to_owned(),
+        };
+        Ok(Response {
+            code: code,
+            url: final_url,
+            fetched_at: fetched_at,
+            duration: duration,
+            headers: headers,
+            body: dst,
+        })
     }
 
     /*
     Tries to fetch and parse the robots file
-    Upon success the robots vector will be populated with forbidded 
-    paths. 
+    Upon success the robots will hold the rules of the group
+    matching our user-agent.
+    As per RFC 9309:
+    4xx means there are no restrictions
+    5xx or network error means we must not crawl for now
      */
-    fn check_robots(&mut self) -> Result<(), DomainError>{
+    fn check_robots(&mut self, cache: Option<&RobotsCache>) -> Result<(), DomainError>{
 
         let robots_url = self.get_robots_url();
+        if let Some(cached) = cache.and_then(|c| c.get(&robots_url)) {
+            log_info(&format!("Using cached robots.txt for {}", self.domain));
+            self.robots = try!(robots_from_response(cached.status, cached.content.as_bytes()));
+            return Ok(());
+        }
+
         log_info(&format!("Fetching robots.txt from {}", robots_url));
-        match self.page_curl(&robots_url) {
-            Ok(res) => {
-                // Extract forbidden URLs from robots
-                for robots_line in res.lines() {
-                    let line: Vec<&str> = robots_line.split_terminator(':').collect();
-                    if line.len() == 2 && line[0].contains("Disallow") {
-                        log_info(&format!("Disallow: {}", line[1]));
-                        self.robots.push(String::from(line[1]));
-                    } else {
-                        return Err(DomainError::RobotsError);
-                    }
+        match self.fetch(&robots_url) {
+            Ok(resp) => {
+                self.robots = try!(robots_from_response(resp.code, &resp.body));
+                // Only successful outcomes are worth remembering
+                if let Some(c) = cache {
+                    let content = String::from_utf8_lossy(&resp.body).into_owned();
+                    c.insert(&robots_url, CachedRobots::new(resp.code, content));
                 }
                 Ok(())
             },
-            Err(err) => Err(err) ,
+            Err(err) => {
+                log_err(&format!("Failed to fetch {}: {}", robots_url, err));
+                Err(DomainError::RobotsUnavailable)
+            },
         }
-    }   
+    }
+
+    /*
+    Seeds the list of URLs to visit with sitemap entries.
+    Sitemaps come from robots.txt or the default /sitemap.xml
+    and sitemap index files are followed recursively.
+    Gives up on the rest once the deadline would pass.
+     */
+    pub fn load_sitemaps(&mut self, deadline: Option<Instant>) {
+        let mut pending: Vec<(String, usize)> = if self.robots.sitemaps().is_empty() {
+            let mut default_url = String::from(self.domain);
+            default_url.push_str("/sitemap.xml");
+            if self.is_url_in_robots(&default_url) {
+                return;
+            }
+            vec![(default_url, 0)]
+        } else {
+            self.robots.sitemaps().iter().map(|s| (s.clone(), 0)).collect()
+        };
+        let mut fetched: Vec<String> = Vec::new();
+
+        while let Some((url, depth)) = pending.pop() {
+            if fetched.len() >= MAX_SITEMAPS {
+                log_warn(&format!("Sitemap limit reached for {}", self.domain));
+                break;
+            }
+            if fetched.contains(&url) {
+                continue;
+            }
+            if deadline.map_or(false, |d| Instant::now() + self.delay >= d) {
+                log_warn(&format!("No time left for sitemaps of {}", self.domain));
+                break;
+            }
+            if !fetched.is_empty() {
+                self.pause();
+            }
+            log_info(&format!("Fetching sitemap from {}", url));
+            // Same body cap as pages, sitemap parser caps what is inside
+            let data = match self.request(&url, None, false, &[]) {
+                Ok(resp) => {
+                    if resp.code < 200 || resp.code >= 300 {
+                        log_warn(&format!("Sitemap {} returned status {}", url, resp.code));
+                        fetched.push(url);
+                        continue;
+                    }
+                    resp.body
+                },
+                Err(err) => {
+                    log_err(&format!("Failed to fetch sitemap {}: {}", url, err));
+                    fetched.push(url);
+                    continue;
+                },
+            };
+            fetched.push(url);
+
+            match Sitemap::from_bytes(&data) {
+                Sitemap::Index(locs) => {
+                    if depth >= MAX_SITEMAP_DEPTH {
+                        log_warn("Sitemap index nested too deep...skipping");
+                        continue;
+                    }
+                    for loc in locs {
+                        pending.push((loc, depth + 1));
+                    }
+                },
+                Sitemap::UrlSet(entries) => {
+                    for entry in entries {
+                        if !self.is_own_url(&entry.loc) {
+                            continue;
+                        }
+                        // Sitemap URLs are treated as linked from the root
+                        self.queue_url(&entry.loc, 1, entry.priority);
+                        let key = self.normalize(&entry.loc);
+                        self.sitemap_entries.insert(key, entry);
+                    }
+                },
+            }
+        }
+        log_info(&format!("Found {} URLs in sitemaps of {}",
+                          self.sitemap_entries.len(), self.domain));
+    }
+}
+
+/*
+Collects "Name: value" header lines.
+Every redirect starts with new status line so previous
+headers are dropped and only the final response is kept.
+ */
+fn parse_header_line(line: &[u8], headers: &mut Vec<(String, String)>) {
+    let line = String::from_utf8_lossy(line);
+    if line.starts_with("HTTP/") {
+        headers.clear();
+        return;
+    }
+    if let Some(idx) = line.find(':') {
+        let name = line[..idx].trim().to_owned();
+        let value = line[idx + 1..].trim().to_owned();
+        if !name.is_empty() {
+            headers.push((name, value));
+        }
+    }
+}
+
+/*
+Retry-After is either number of seconds or HTTP date
+ */
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    time::strptime(value, "%a, %d %b %Y %H:%M:%S GMT").ok().map(|tm| {
+        let wait = tm.to_timespec().sec - time::get_time().sec;
+        // Date in the past means now
+        Duration::from_secs(if wait > 0 { wait as u64 } else { 0 })
+    })
+}
+
+/*
+Status code out of "HTTP/1.1 200 OK" line, 0 if malformed
+ */
+fn status_code(line: &[u8]) -> u32 {
+    String::from_utf8_lossy(line)
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .unwrap_or(0)
+}
+
+/*
+All values of the header, name is case insensitive
+ */
+fn header_values<'a>(headers: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    headers.iter()
+        .filter(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, ref v)| v.as_str())
+        .collect()
 }
 
+/*
+Interpret robots.txt response according to its status code
+ */
+fn robots_from_response(code: u32, content: &[u8]) -> Result<Robots, DomainError> {
+    match code {
+        200...299 => Ok(Robots::parse(&String::from_utf8_lossy(content), USER_AGENT)),
+        400...499 => {
+            log_info(&format!("robots.txt returned {}. No restrictions", code));
+            Ok(Robots::allow_all())
+        },
+        _ => {
+            log_warn(&format!("robots.txt returned {}. Retry later", code));
+            Err(DomainError::RobotsUnavailable)
+        },
+    }
+}
 
 #[cfg(test)]
 #[test]
 fn test_is_in_robots() {
     let mut dom = Domain::new("http://exampdssdsdle.com");
-    dom.robots.push("/aaa".to_string());
-    dom.robots.push("/bbb".to_string());
-    dom.robots.push("/ccc".to_string());
-
-    assert!(dom.is_url_in_robots("http://example.com/fff/aaa"));
+    dom.robots = Robots::parse("User-agent: *\n\
+                                Disallow: /aaa\n\
+                                Disallow: /bbb\n\
+                                Allow: /bbb/zzz\n\
+                                Disallow: /ccc\n", USER_AGENT);
+
+    assert!(dom.is_url_in_robots("http://example.com/aaa"));
+    assert!(!dom.is_url_in_robots("http://example.com/fff/aaa"));
     assert!(!dom.is_url_in_robots("http://example.com/fff/ggg"));
-    assert!(dom.is_url_in_robots("http://example.com/fff/bbb/zzz"));
+    assert!(dom.is_url_in_robots("http://example.com/bbb/yyy"));
+    assert!(!dom.is_url_in_robots("http://example.com/bbb/zzz"));
 }
 
 
@@ -194,12 +787,113 @@ fn test_add_to_visit() {
     dom.add_to_visit("http://example.com/fff");
     assert_eq!(dom.paths_to_visit.len(), 2);
 
+    // Same page just written differently
+    dom.add_to_visit("http://EXAMPLE.com:80/./fff#top");
+    assert_eq!(dom.paths_to_visit.len(), 2);
+
+    // Already visited
+    dom.add_visited("http://example.com/hhh?utm_source=feed");
+    dom.add_to_visit("http://example.com/hhh");
+    assert_eq!(dom.paths_to_visit.len(), 2);
+
     // Should be added
     dom.add_to_visit("http://example.com/ggg");
     assert_eq!(dom.paths_to_visit.len(), 3);
+
+    // Too deep
+    dom.max_depth = Some(3);
+    dom.current_depth = 3;
+    dom.add_to_visit("http://example.com/deep");
+    dom.add_to_visit("http://example.com/deep");
+    assert_eq!(dom.paths_to_visit.len(), 3);
+    assert_eq!(dom.depth_truncated, 1);
+    // Already queued or seen through shorter path
+    dom.add_to_visit("http://example.com/ggg");
+    dom.add_to_visit("http://example.com/hhh");
+    assert_eq!(dom.depth_truncated, 1);
+
+    // Crawler trap
+    dom.current_depth = 0;
+    dom.add_to_visit("http://example.com/a/b/a/b/a/b");
+    assert_eq!(dom.paths_to_visit.len(), 3);
+    assert_eq!(dom.traps_rejected, 1);
+
+    // Found again on another page, already counted
+    dom.add_to_visit("http://example.com/a/b/a/b/a/b");
+    assert_eq!(dom.traps_rejected, 1);
+}
+
+#[test]
+fn test_calendar_trap() {
+    // Default breadth first order, nothing to deprioritize into
+    let mut dom = Domain::new("");
+    let limit = TrapOptions::default().max_param_values;
+    for month in 0..limit + 50 {
+        dom.add_to_visit(&format!("http://example.com/calendar?month={}", month));
+    }
+    assert_eq!(dom.traps_rejected, 50);
+    assert_eq!(dom.paths_to_visit.len(), limit + 1);
 }
 
 
+#[test]
+fn test_is_own_url() {
+    let dom = Domain::new("http://exampdssdsdle.com");
+
+    assert!(dom.is_own_url("http://exampdssdsdle.com"));
+    assert!(dom.is_own_url("http://exampdssdsdle.com/aaa"));
+    assert!(!dom.is_own_url("http://exampdssdsdle.com.evil.com/aaa"));
+    assert!(!dom.is_own_url("http://other.com/aaa"));
+}
+
+#[test]
+fn test_parse_header_line() {
+    let mut headers = Vec::new();
+    parse_header_line(b"HTTP/1.1 301 Moved Permanently\r\n", &mut headers);
+    parse_header_line(b"Location: http://example.com/\r\n", &mut headers);
+    parse_header_line(b"HTTP/1.1 200 OK\r\n", &mut headers);
+    parse_header_line(b"Content-Type: text/html; charset=UTF-8\r\n", &mut headers);
+    parse_header_line(b"X-Robots-Tag: noindex\r\n", &mut headers);
+    parse_header_line(b"\r\n", &mut headers);
+
+    let resp = Response {
+        code: 200,
+        url: "http://example.com/".to_owned(),
+        fetched_at: 0,
+        duration: Duration::from_secs(0),
+        headers: headers,
+        body: Vec::new(),
+    };
+    assert_eq!(resp.headers.len(), 2);
+    assert_eq!(resp.header("x-robots-tag"), vec!["noindex"]);
+    assert!(resp.header("Location").is_empty());
+}
+
+#[test]
+fn test_robots_from_response() {
+    let content = b"User-agent: *\nDisallow: /private\n";
+
+    let robots = robots_from_response(200, content).unwrap();
+    assert!(!robots.is_allowed("http://example.com/private"));
+
+    // HTML error page must not be taken as rules
+    let robots = robots_from_response(404, b"<html>Not found: /</html>").unwrap();
+    assert!(robots.is_allowed("http://example.com/private"));
+    assert!(robots_from_response(403, content).unwrap().is_allowed("http://example.com/private"));
+
+    assert!(robots_from_response(503, content).is_err());
+    assert!(robots_from_response(500, content).is_err());
+}
+
+#[test]
+fn test_unavailable_robots() {
+    // Cant even resolve it so everything is forbidden for now
+    let dom = Domain::new("http://exampdssdsdle.invalid");
+
+    assert!(dom.robots_unavailable());
+    assert!(dom.is_url_in_robots("http://exampdssdsdle.invalid/aaa"));
+}
+
 #[test]
 fn test_get_webpage() {
 
@@ -207,7 +901,7 @@ fn test_get_webpage() {
     let curl_result = dom.get_webpage(dom.domain);
 
     assert!(curl_result.is_ok());
-    dom.robots.push("domains".to_owned());
+    dom.robots = Robots::parse("User-agent: *\nDisallow: /domains\n", USER_AGENT);
     // domins is in dissalowd list so this should fail
     // we are cheating with the domain though ;)
     let curl_result = dom.get_webpage("http://www.iana.org/domains/reserved");
@@ -217,3 +911,41 @@ fn test_get_webpage() {
     let curl_result = dom.get_webpage("");
     assert!(curl_result.is_err());
 }
+
+#[test]
+fn test_status_code() {
+    assert_eq!(status_code(b"HTTP/1.1 200 OK\r\n"), 200);
+    assert_eq!(status_code(b"HTTP/2 301\r\n"), 301);
+    assert_eq!(status_code(b"HTTP/1.1\r\n"), 0);
+}
+
+#[test]
+fn test_parse_retry_after() {
+    assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_retry_after(" 0 "), Some(Duration::from_secs(0)));
+    assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+               Some(Duration::from_secs(0)));
+    assert_eq!(parse_retry_after("soon"), None);
+
+    let later = time::at_utc(time::Timespec::new(time::get_time().sec + 3600, 0));
+    let header = time::strftime("%a, %d %b %Y %H:%M:%S GMT", &later).unwrap();
+    let wait = parse_retry_after(&header).unwrap();
+    assert!(wait > Duration::from_secs(3590) && wait <= Duration::from_secs(3600));
+}
+
+#[test]
+fn test_new_page() {
+    let headers = vec![("Content-Type".to_owned(), "text/html".to_owned()),
+                       ("Set-Cookie".to_owned(), "a=1".to_owned()),
+                       ("set-cookie".to_owned(), "b=2".to_owned())];
+    let page = Page::new("http://example.com/", 200, headers, b"<p>hi</p>".to_vec());
+
+    assert_eq!(page.final_url, "http://example.com/");
+    assert_eq!(page.content_type, Some("text/html".to_owned()));
+    assert_eq!(page.header("SET-COOKIE"), vec!["a=1", "b=2"]);
+    assert_eq!(page.size, 9);
+    assert_eq!(page.text(), "<p>hi</p>");
+    assert_eq!(page.encoding, "utf-8");
+    assert!(!page.is_not_modified());
+    assert!(page.fetched_at > 0);
+}
@@ -0,0 +1,89 @@
+// Crates
+
+// Modules
+
+// Standard lib
+
+// Project libs
+
+/*
+What we remember about the page between crawls
+so it does not have to be downloaded again
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageRecord {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // Fingerprint of the decoded body
+    pub content_hash: u64,
+    // Links of the page pointing to the domain we crawl
+    pub outlinks: Vec<String>,
+    // Unix timestamp in seconds
+    pub fetched_at: i64,
+}
+
+impl PageRecord {
+    /*
+    Headers making the request conditional
+     */
+    pub fn conditional_headers(&self) -> Vec<String> {
+        let mut headers = Vec::new();
+        if let Some(ref etag) = self.etag {
+            headers.push(format!("If-None-Match: {}", etag));
+        }
+        if let Some(ref modified) = self.last_modified {
+            headers.push(format!("If-Modified-Since: {}", modified));
+        }
+        headers
+    }
+}
+
+/*
+64-bit FNV-1a hash of the content. Unlike std hashers it is the
+same on every build, so stored hashes stay comparable.
+ */
+pub fn content_hash(text: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in text.as_bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/*
+Persistent storage of page records, shared by all threads
+ */
+pub trait PageStore: Send + Sync {
+    fn load_page(&self, url: &str) -> Option<PageRecord>;
+    fn save_page(&self, record: &PageRecord);
+}
+
+#[cfg(test)]
+#[test]
+fn test_conditional_headers() {
+    let mut record = PageRecord {
+        url: "http://example.com/".to_owned(),
+        etag: Some("\"abc\"".to_owned()),
+        last_modified: Some("Wed, 21 Oct 2015 07:28:00 GMT".to_owned()),
+        content_hash: 1,
+        outlinks: Vec::new(),
+        fetched_at: 0,
+    };
+    assert_eq!(record.conditional_headers(),
+               vec!["If-None-Match: \"abc\"".to_owned(),
+                    "If-Modified-Since: Wed, 21 Oct 2015 07:28:00 GMT".to_owned()]);
+
+    record.etag = None;
+    record.last_modified = None;
+    assert!(record.conditional_headers().is_empty());
+}
+
+#[test]
+fn test_content_hash() {
+    // Reference values of FNV-1a 64
+    assert_eq!(content_hash(""), 0xcbf29ce484222325);
+    assert_eq!(content_hash("a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(content_hash("foobar"), 0x85944171f73967e8);
+}
@@ -0,0 +1,489 @@
+// Crates
+
+// Modules
+
+// Standard lib
+use std::time::Duration;
+
+// Project libs
+use domain::domain_url::normalize_percent_encoding;
+
+/*
+Single Allow/Disallow rule from robots.txt
+ */
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/*
+Parsed robots.txt (RFC 9309)
+Only the rules of the group(s) matching our user-agent are kept.
+If there is no such group the rules of the `*` group are used.
+ */
+#[derive(Debug, Clone)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    delay: Option<Duration>,
+    sitemaps: Vec<String>,
+}
+
+impl Robots {
+
+    /*
+    No restrictions at all
+     */
+    pub fn allow_all() -> Robots {
+        Robots {
+            rules: Vec::new(),
+            delay: None,
+            sitemaps: Vec::new(),
+        }
+    }
+
+    /*
+    Everything is forbidden
+     */
+    pub fn disallow_all() -> Robots {
+        Robots {
+            rules: vec![Rule { allow: false, pattern: "/".to_owned() }],
+            delay: None,
+            sitemaps: Vec::new(),
+        }
+    }
+
+    pub fn parse(content: &str, user_agent: &str) -> Robots {
+        let agent = product_token(user_agent);
+
+        // Rules of groups addressed to us and to everyone
+        let mut ours: Vec<Rule> = Vec::new();
+        let mut any: Vec<Rule> = Vec::new();
+        let mut ours_delay: Option<Duration> = None;
+        let mut any_delay: Option<Duration> = None;
+        let mut found_ours = false;
+        let mut sitemaps: Vec<String> = Vec::new();
+
+        // State of the group we are currently in
+        let mut in_agents = false;
+        let mut is_ours = false;
+        let mut is_any = false;
+
+        for raw_line in content.lines() {
+            // Strip comments and whitespace
+            let line = match raw_line.find('#') {
+                Some(idx) => &raw_line[..idx],
+                None => raw_line,
+            }.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = match line.find(':') {
+                Some(idx) => (line[..idx].trim().to_lowercase(),
+                              line[idx + 1..].trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    // User-agent line after rules starts new group
+                    if !in_agents {
+                        in_agents = true;
+                        is_ours = false;
+                        is_any = false;
+                    }
+                    let name = product_token(value);
+                    if name == "*" {
+                        is_any = true;
+                    } else if name.len() > 0 && name == agent {
+                        is_ours = true;
+                        found_ours = true;
+                    }
+                },
+                "allow" | "disallow" => {
+                    in_agents = false;
+                    // Empty value means no restriction
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rule = Rule {
+                        allow: key == "allow",
+                        pattern: normalize_path(value),
+                    };
+                    if is_ours {
+                        ours.push(rule.clone());
+                    }
+                    if is_any {
+                        any.push(rule);
+                    }
+                },
+                "crawl-delay" | "request-rate" => {
+                    in_agents = false;
+                    let delay = if key == "crawl-delay" {
+                        parse_crawl_delay(value)
+                    } else {
+                        parse_request_rate(value)
+                    };
+                    if let Some(d) = delay {
+                        // If both are given go with the slower one
+                        if is_ours {
+                            ours_delay = Some(max_delay(ours_delay, d));
+                        }
+                        if is_any {
+                            any_delay = Some(max_delay(any_delay, d));
+                        }
+                    }
+                },
+                "sitemap" => {
+                    // Sitemaps are not part of any group
+                    if !value.is_empty() {
+                        sitemaps.push(value.to_owned());
+                    }
+                },
+                _ => {
+                    // Any other record ends the list of user-agents
+                    // so the next user-agent starts a new group
+                    in_agents = false;
+                },
+            }
+        }
+
+        if found_ours {
+            Robots { rules: ours, delay: ours_delay, sitemaps: sitemaps }
+        } else {
+            Robots { rules: any, delay: any_delay, sitemaps: sitemaps }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /*
+    Delay between requests asked for by Crawl-delay or Request-rate
+     */
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.delay
+    }
+
+    /*
+    Sitemap URLs listed in robots.txt
+     */
+    pub fn sitemaps(&self) -> &Vec<String> {
+        &self.sitemaps
+    }
+
+    /*
+    Checks the path (with query) of the URL against the rules.
+    Most specific (longest) matching rule wins, Allow wins a tie.
+     */
+    pub fn is_allowed(&self, url: &str) -> bool {
+        let path = normalize_path(url_path(url));
+        let path = path.as_str();
+
+        // robots.txt itself is always allowed
+        if path == "/robots.txt" {
+            return true;
+        }
+
+        let mut best: Option<&Rule> = None;
+        for rule in self.rules.iter() {
+            if !pattern_matches(&rule.pattern, path) {
+                continue;
+            }
+            best = match best {
+                None => Some(rule),
+                Some(b) => {
+                    if rule.pattern.len() > b.pattern.len()
+                        || (rule.pattern.len() == b.pattern.len() && rule.allow) {
+                        Some(rule)
+                    } else {
+                        Some(b)
+                    }
+                },
+            };
+        }
+
+        match best {
+            Some(rule) => rule.allow,
+            None => true,
+        }
+    }
+}
+
+fn max_delay(current: Option<Duration>, new: Duration) -> Duration {
+    match current {
+        Some(d) if d > new => d,
+        _ => new,
+    }
+}
+
+fn secs_to_duration(secs: f64) -> Option<Duration> {
+    if secs.is_finite() && secs >= 0.0 {
+        Some(Duration::from_millis((secs * 1000.0) as u64))
+    } else {
+        None
+    }
+}
+
+/*
+Crawl-delay: 10 or Crawl-delay: 0.5 (seconds)
+ */
+fn parse_crawl_delay(value: &str) -> Option<Duration> {
+    value.parse::<f64>().ok().and_then(secs_to_duration)
+}
+
+/*
+Request-rate: 1/5 means one request every 5 seconds.
+Period can have s, m or h suffix i.e. 10/1m
+ */
+fn parse_request_rate(value: &str) -> Option<Duration> {
+    // Some sites append time window i.e. "1/5 0600-0845"
+    let value = value.split_whitespace().next().unwrap_or("");
+    let mut parts = value.splitn(2, '/');
+    let requests = match parts.next().and_then(|r| r.trim().parse::<f64>().ok()) {
+        Some(r) if r > 0.0 => r,
+        _ => return None,
+    };
+    let period = parts.next().unwrap_or("").trim().to_lowercase();
+    let (number, multiplier) = if period.ends_with('h') {
+        (&period[..period.len() - 1], 3600.0)
+    } else if period.ends_with('m') {
+        (&period[..period.len() - 1], 60.0)
+    } else if period.ends_with('s') {
+        (&period[..period.len() - 1], 1.0)
+    } else {
+        (&period[..], 1.0)
+    };
+    match number.parse::<f64>() {
+        Ok(secs) => secs_to_duration(secs * multiplier / requests),
+        Err(_) => None,
+    }
+}
+
+/*
+Extract the product token i.e "mokosza" out of "Mokosza/1.0 (+http://...)"
+ */
+fn product_token(user_agent: &str) -> String {
+    user_agent.trim()
+        .split(|c: char| c == '/' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/*
+Returns the path and query part of the URL.
+Fragment is never sent to the server so its dropped.
+ */
+fn url_path(url: &str) -> &str {
+    let rest = match url.find("://") {
+        Some(idx) => {
+            let after = &url[idx + 3..];
+            match after.find('/') {
+                Some(p) => &after[p..],
+                None => "/",
+            }
+        },
+        None => url,
+    };
+
+    let rest = match rest.find('#') {
+        Some(idx) => &rest[..idx],
+        None => rest,
+    };
+
+    if rest.is_empty() { "/" } else { rest }
+}
+
+/*
+Brings path or pattern to the form RFC 9309 compares them in.
+Escapes of unreserved characters are decoded, other escapes
+get uppercase hex and non-ASCII bytes are percent-encoded.
+ */
+fn normalize_path(s: &str) -> String {
+    let decoded = normalize_percent_encoding(s);
+    let mut out = String::with_capacity(decoded.len());
+    for b in decoded.bytes() {
+        if b < 0x80 {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/*
+Match robots pattern against path.
+`*` matches any sequence of characters and `$` at the end
+anchors the pattern to the end of the path.
+Otherwise pattern only needs to match the beginning of the path.
+ */
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = if pattern.ends_with('$') {
+        (&pattern[..pattern.len() - 1], true)
+    } else {
+        (pattern, false)
+    };
+
+    let p = pattern.as_bytes();
+    let s = path.as_bytes();
+    let mut pi = 0;
+    let mut si = 0;
+    // Position of last seen `*` in pattern and path
+    let mut star: Option<(usize, usize)> = None;
+
+    while si < s.len() {
+        if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, si));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == s[si] {
+            pi += 1;
+            si += 1;
+        } else if pi == p.len() && !anchored {
+            return true;
+        } else if let Some((sp, ss)) = star {
+            // Let the `*` eat one more character
+            pi = sp + 1;
+            si = ss + 1;
+            star = Some((sp, ss + 1));
+        } else {
+            return false;
+        }
+    }
+
+    while pi < p.len() && p[pi] == b'*' {
+        pi += 1;
+    }
+    pi == p.len()
+}
+
+#[cfg(test)]
+#[test]
+fn test_pattern_matches() {
+    assert!(pattern_matches("/fish", "/fish"));
+    assert!(pattern_matches("/fish", "/fish.html"));
+    assert!(pattern_matches("/fish", "/fishheads/yummy.html"));
+    assert!(!pattern_matches("/fish", "/Fish.asp"));
+    assert!(!pattern_matches("/fish", "/catfish"));
+
+    assert!(pattern_matches("/*.php", "/index.php"));
+    assert!(pattern_matches("/*.php", "/folder/filename.php?parameters"));
+    assert!(!pattern_matches("/*.php", "/windows.PHP"));
+
+    assert!(pattern_matches("/*.php$", "/filename.php"));
+    assert!(!pattern_matches("/*.php$", "/filename.php?parameters"));
+    assert!(!pattern_matches("/*.php$", "/filename.php5"));
+
+    assert!(pattern_matches("/fish*.php", "/fishheads/catfish.php?parameters"));
+    assert!(!pattern_matches("/fish*.php", "/Fish.PHP"));
+}
+
+#[test]
+fn test_percent_encoding() {
+    let content = "User-agent: *\n\
+                   Disallow: /a%3cd\n\
+                   Disallow: /%7Ejoe\n\
+                   Disallow: /foo/bar/\u{17c}\n";
+    let robots = Robots::parse(content, "mokosza");
+    assert!(!robots.is_allowed("http://example.com/a%3Cd"));
+    assert!(!robots.is_allowed("http://example.com/a%3cd"));
+    assert!(!robots.is_allowed("http://example.com/~joe/index.html"));
+    assert!(!robots.is_allowed("http://example.com/foo/bar/%C5%BC"));
+    assert!(robots.is_allowed("http://example.com/a%3Ce"));
+}
+
+#[test]
+fn test_parse_groups() {
+    let content = "# comment\n\
+                   User-agent: googlebot\n\
+                   Disallow: /google\n\
+                   \n\
+                   User-agent: *\n\
+                   Disallow: /private # trailing comment\n\
+                   Allow: /private/public\n\
+                   Sitemap: http://example.com/sitemap.xml\n\
+                   \n\
+                   User-agent: Mokosza\n\
+                   User-agent: otherbot\n\
+                   Disallow: /nomokosza\n";
+
+    // Our own group wins over `*`
+    let robots = Robots::parse(content, "mokosza/0.1");
+    assert_eq!(robots.len(), 1);
+    assert!(!robots.is_allowed("http://example.com/nomokosza/page"));
+    assert!(robots.is_allowed("http://example.com/private"));
+    assert!(robots.is_allowed("http://example.com/google"));
+
+    // Unknown agent falls back to `*`
+    let robots = Robots::parse(content, "somebot");
+    assert_eq!(robots.len(), 2);
+    assert!(!robots.is_allowed("http://example.com/private/stuff"));
+    assert!(robots.is_allowed("http://example.com/private/public/stuff"));
+    assert!(robots.is_allowed("http://example.com/google"));
+    assert!(robots.is_allowed("http://example.com/"));
+    assert_eq!(robots.sitemaps(), &vec!["http://example.com/sitemap.xml".to_owned()]);
+}
+
+#[test]
+fn test_longest_match() {
+    let content = "User-agent: *\n\
+                   Allow: /p\n\
+                   Disallow: /\n\
+                   Disallow: /folder/\n\
+                   Allow: /folder\n\
+                   Disallow: /*.htm$\n\
+                   Allow: /page\n\
+                   Disallow: /page\n";
+    let robots = Robots::parse(content, "mokosza");
+    assert!(robots.is_allowed("http://example.com/page"));
+    assert!(!robots.is_allowed("http://example.com/folder/page"));
+    assert!(!robots.is_allowed("http://example.com/other.htm"));
+    assert!(!robots.is_allowed("http://example.com"));
+    assert!(robots.is_allowed("http://example.com/robots.txt"));
+}
+
+#[test]
+fn test_crawl_delay() {
+    let content = "User-agent: *\n\
+                   Crawl-delay: 2\n\
+                   \n\
+                   User-agent: mokosza\n\
+                   Crawl-delay: 0.5\n\
+                   Request-rate: 1/10s\n";
+    let robots = Robots::parse(content, "mokosza");
+    assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(10000)));
+
+    let robots = Robots::parse(content, "otherbot");
+    assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(2000)));
+
+    let robots = Robots::parse("User-agent: *\nCrawl-delay: 0.5\n", "mokosza");
+    assert_eq!(robots.crawl_delay(), Some(Duration::from_millis(500)));
+
+    let robots = Robots::parse("User-agent: *\nCrawl-delay: soon\n", "mokosza");
+    assert_eq!(robots.crawl_delay(), None);
+
+    assert_eq!(parse_request_rate("30/1m"), Some(Duration::from_millis(2000)));
+    assert_eq!(parse_request_rate("1/5 0600-0845"), Some(Duration::from_millis(5000)));
+    assert_eq!(parse_request_rate("0/5"), None);
+}
+
+#[test]
+fn test_empty_robots() {
+    let robots = Robots::parse("", "mokosza");
+    assert!(robots.is_allowed("http://example.com/anything"));
+
+    // Empty disallow means everything is allowed
+    let robots = Robots::parse("User-agent: *\nDisallow:\n", "mokosza");
+    assert!(robots.is_allowed("http://example.com/anything"));
+
+    let robots = Robots::allow_all();
+    assert!(robots.is_allowed("http://example.com/anything"));
+
+    let robots = Robots::disallow_all();
+    assert!(!robots.is_allowed("http://example.com/anything"));
+    assert!(!robots.is_allowed("http://example.com"));
+}
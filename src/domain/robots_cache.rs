@@ -0,0 +1,228 @@
+// Crates
+extern crate time;
+
+// Modules
+
+// Standard lib
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Project libs
+use domain::domain_url::Origin;
+use log::*;
+
+// RFC 9309 says cached robots.txt should not be used for more than 24h
+pub const DEFAULT_TTL_SECS: u64 = 86400;
+// Hosts kept in memory, the persistent store has the rest
+pub const DEFAULT_MAX_ENTRIES: usize = 10000;
+
+/*
+Raw robots.txt response as we got it from the server.
+Its kept unparsed so it can be persisted and used with any user-agent.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachedRobots {
+    pub status: u32,
+    pub content: String,
+    // Unix timestamp in seconds
+    pub fetched_at: i64,
+}
+
+impl CachedRobots {
+    pub fn new(status: u32, content: String) -> CachedRobots {
+        CachedRobots {
+            status: status,
+            content: content,
+            fetched_at: time::get_time().sec,
+        }
+    }
+}
+
+/*
+Optional persistent storage for the cache so the rules
+survive restarts and are shared between instances
+ */
+pub trait RobotsStore: Send + Sync {
+    fn load_robots(&self, key: &str) -> Option<CachedRobots>;
+    fn save_robots(&self, key: &str, robots: &CachedRobots);
+}
+
+/*
+Thread safe robots.txt cache keyed by scheme, host and port
+ */
+pub struct RobotsCache {
+    entries: Mutex<HashMap<String, CachedRobots>>,
+    ttl: Duration,
+    max_entries: usize,
+    store: Option<Box<RobotsStore>>,
+}
+
+impl RobotsCache {
+    pub fn new(ttl: Duration) -> RobotsCache {
+        RobotsCache {
+            entries: Mutex::new(HashMap::new()),
+            ttl: ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            store: None,
+        }
+    }
+
+    pub fn with_store(ttl: Duration, store: Box<RobotsStore>) -> RobotsCache {
+        RobotsCache {
+            entries: Mutex::new(HashMap::new()),
+            ttl: ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            store: Some(store),
+        }
+    }
+
+    fn is_fresh(&self, robots: &CachedRobots) -> bool {
+        time::get_time().sec - robots.fetched_at < self.ttl.as_secs() as i64
+    }
+
+    /*
+    Keeps the entry in memory. When the cache is full expired
+    entries are dropped first, then the oldest one.
+     */
+    fn remember(&self, key: String, robots: CachedRobots) {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.retain(|_, cached| self.is_fresh(cached));
+            if entries.len() >= self.max_entries {
+                let oldest = entries.iter()
+                    .min_by_key(|&(_, cached)| cached.fetched_at)
+                    .map(|(k, _)| k.clone());
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+        }
+        entries.insert(key, robots);
+    }
+
+    /*
+    Returns cached robots.txt for the URL if we have one that did not expire.
+    Memory is checked first then the persistent store.
+     */
+    pub fn get(&self, url: &str) -> Option<CachedRobots> {
+        let key = match origin_key(url) {
+            Some(k) => k,
+            None => return None,
+        };
+
+        if let Ok(entries) = self.entries.lock() {
+            if let Some(robots) = entries.get(&key) {
+                if self.is_fresh(robots) {
+                    return Some(robots.clone());
+                }
+            }
+        }
+
+        let stored = match self.store {
+            Some(ref store) => store.load_robots(&key),
+            None => None,
+        };
+        match stored {
+            Some(robots) => {
+                if !self.is_fresh(&robots) {
+                    return None;
+                }
+                log_info(&format!("Using stored robots.txt for {}", key));
+                self.remember(key, robots.clone());
+                Some(robots)
+            },
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, url: &str, robots: CachedRobots) {
+        let key = match origin_key(url) {
+            Some(k) => k,
+            None => return,
+        };
+
+        if let Some(ref store) = self.store {
+            store.save_robots(&key, &robots);
+        }
+        self.remember(key, robots);
+    }
+
+    pub fn len(&self) -> usize {
+        match self.entries.lock() {
+            Ok(entries) => entries.len(),
+            Err(_) => 0,
+        }
+    }
+}
+
+/*
+Builds "scheme://host:port" out of URL.
+robots.txt is only valid for this exact combination.
+ */
+pub fn origin_key(url: &str) -> Option<String> {
+    Origin::parse(url).map(|origin| {
+        format!("{}://{}:{}", origin.scheme, origin.host, origin.effective_port())
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn test_origin_key() {
+    assert_eq!(origin_key("http://www.Example.com"),
+               Some("http://www.example.com:80".to_owned()));
+    assert_eq!(origin_key("http://www.example.com:80/aaa?b=c"),
+               Some("http://www.example.com:80".to_owned()));
+    assert_eq!(origin_key("HTTPS://user@example.com/robots.txt"),
+               Some("https://example.com:443".to_owned()));
+    assert_eq!(origin_key("http://example.com:8080"),
+               Some("http://example.com:8080".to_owned()));
+    assert_eq!(origin_key("example.com"), None);
+    assert_eq!(origin_key("http://"), None);
+}
+
+#[test]
+fn test_cache_expiry() {
+    let cache = RobotsCache::new(Duration::from_secs(DEFAULT_TTL_SECS));
+    let robots = CachedRobots::new(200, "User-agent: *\nDisallow: /\n".to_owned());
+
+    cache.insert("http://example.com/robots.txt", robots.clone());
+    assert_eq!(cache.get("http://example.com/aaa"), Some(robots.clone()));
+    assert_eq!(cache.get("http://EXAMPLE.com:80"), Some(robots.clone()));
+    assert_eq!(cache.get("https://example.com/aaa"), None);
+
+    // Fetched two days ago
+    let mut old = robots.clone();
+    old.fetched_at -= 2 * DEFAULT_TTL_SECS as i64;
+    cache.insert("http://example.com", old);
+    assert_eq!(cache.get("http://example.com/aaa"), None);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_cache_eviction() {
+    let mut cache = RobotsCache::new(Duration::from_secs(DEFAULT_TTL_SECS));
+    cache.max_entries = 2;
+    let robots = CachedRobots::new(200, String::new());
+    let mut expired = robots.clone();
+    expired.fetched_at -= 2 * DEFAULT_TTL_SECS as i64;
+    let mut older = robots.clone();
+    older.fetched_at -= 10;
+
+    cache.insert("http://a.com", expired);
+    cache.insert("http://b.com", robots.clone());
+    // Expired one goes first
+    cache.insert("http://c.com", older);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("http://b.com").is_some());
+    assert!(cache.get("http://c.com").is_some());
+
+    // Then the oldest one
+    cache.insert("http://d.com", robots.clone());
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("http://c.com").is_none());
+    assert!(cache.get("http://d.com").is_some());
+}
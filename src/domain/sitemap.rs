@@ -0,0 +1,235 @@
+// Crates
+extern crate flate2;
+
+// Modules
+
+// Standard lib
+use std::io::Read;
+use self::flate2::read::GzDecoder;
+
+// Project libs
+
+// Sitemaps protocol limits a sitemap to 50MB uncompressed and 50k URLs
+pub const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+pub const MAX_SITEMAP_ENTRIES: usize = 50000;
+
+/*
+Single <url> entry of the sitemap
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SitemapEntry {
+    pub loc: String,
+    pub lastmod: Option<String>,
+    pub priority: Option<f32>,
+}
+
+/*
+Sitemap is either a list of pages or
+an index pointing to other sitemaps
+ */
+#[derive(Debug, PartialEq)]
+pub enum Sitemap {
+    Index(Vec<String>),
+    UrlSet(Vec<SitemapEntry>),
+}
+
+impl Sitemap {
+
+    /*
+    Parse raw sitemap content. Gzipped content is
+    recognized by its magic bytes not by the file extension
+    as servers tend to decompress it on the fly.
+     */
+    pub fn from_bytes(data: &[u8]) -> Sitemap {
+        if data.len() > 2 && data[0] == 0x1f && data[1] == 0x8b {
+            match gunzip(data, MAX_SITEMAP_BYTES) {
+                Some(content) => Sitemap::parse(&String::from_utf8_lossy(&content)),
+                None => Sitemap::UrlSet(Vec::new()),
+            }
+        } else {
+            let end = ::std::cmp::min(data.len() as u64, MAX_SITEMAP_BYTES) as usize;
+            Sitemap::parse(&String::from_utf8_lossy(&data[..end]))
+        }
+    }
+
+    /*
+    Only the first MAX_SITEMAP_ENTRIES entries are kept
+     */
+    pub fn parse(content: &str) -> Sitemap {
+        if content.contains("<sitemapindex") {
+            let locs = blocks(content, "sitemap", MAX_SITEMAP_ENTRIES).into_iter()
+                .filter_map(|b| tag_text(b, "loc"))
+                .collect();
+            return Sitemap::Index(locs);
+        }
+
+        let entries = blocks(content, "url", MAX_SITEMAP_ENTRIES).into_iter()
+            .filter_map(|b| {
+                tag_text(b, "loc").map(|loc| SitemapEntry {
+                    loc: loc,
+                    lastmod: tag_text(b, "lastmod"),
+                    priority: tag_text(b, "priority").and_then(|p| p.parse().ok()),
+                })
+            })
+            .collect();
+        Sitemap::UrlSet(entries)
+    }
+}
+
+/*
+Decompresses at most limit bytes so a gzip bomb can not
+eat all the memory. Anything past the limit is dropped.
+ */
+fn gunzip(data: &[u8], limit: u64) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    match GzDecoder::new(data).and_then(|dec| dec.take(limit).read_to_end(&mut content)) {
+        Ok(_) => Some(content),
+        Err(_) => None,
+    }
+}
+
+/*
+Returns content of at most limit <tag>...</tag> elements.
+Makes sure <url> does not match <urlset> etc.
+ */
+fn blocks<'a>(content: &'a str, tag: &str, limit: usize) -> Vec<&'a str> {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut found = Vec::new();
+    let mut rest = content;
+
+    while found.len() < limit {
+        let start = match rest.find(&open) {
+            Some(start) => start,
+            None => break,
+        };
+        let after = &rest[start + open.len()..];
+        match after.chars().next() {
+            Some(c) if c == '>' || c.is_whitespace() => {},
+            _ => {
+                rest = after;
+                continue;
+            },
+        }
+        let body_start = match after.find('>') {
+            Some(idx) => idx + 1,
+            None => break,
+        };
+        let body = &after[body_start..];
+        match body.find(&close) {
+            Some(end) => {
+                found.push(&body[..end]);
+                rest = &body[end + close.len()..];
+            },
+            None => break,
+        }
+    }
+    found
+}
+
+/*
+Text of the first <tag> inside the block, with
+CDATA and entities taken care of
+ */
+fn tag_text(block: &str, tag: &str) -> Option<String> {
+    let text = match blocks(block, tag, 1).into_iter().next() {
+        Some(t) => t.trim(),
+        None => return None,
+    };
+
+    let text = if text.starts_with("<![CDATA[") && text.ends_with("]]>") {
+        text[9..text.len() - 3].trim().to_owned()
+    } else {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    };
+
+    if text.is_empty() { None } else { Some(text) }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_urlset() {
+    let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>http://www.example.com/</loc>
+    <lastmod>2005-01-01</lastmod>
+    <priority>0.8</priority>
+  </url>
+  <url>
+    <loc>http://www.example.com/catalog?item=12&amp;desc=vacation_hawaii</loc>
+  </url>
+  <url><loc><![CDATA[http://www.example.com/cdata]]></loc></url>
+  <url><lastmod>2005-01-01</lastmod></url>
+</urlset>"#;
+
+    match Sitemap::parse(content) {
+        Sitemap::UrlSet(entries) => {
+            assert_eq!(entries.len(), 3);
+            assert_eq!(entries[0], SitemapEntry {
+                loc: "http://www.example.com/".to_owned(),
+                lastmod: Some("2005-01-01".to_owned()),
+                priority: Some(0.8),
+            });
+            assert_eq!(entries[1].loc,
+                       "http://www.example.com/catalog?item=12&desc=vacation_hawaii");
+            assert_eq!(entries[1].priority, None);
+            assert_eq!(entries[2].loc, "http://www.example.com/cdata");
+        },
+        Sitemap::Index(_) => panic!("urlset parsed as index"),
+    }
+}
+
+#[test]
+fn test_parse_index() {
+    let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap>
+    <loc>http://www.example.com/sitemap1.xml.gz</loc>
+    <lastmod>2004-10-01T18:23:17+00:00</lastmod>
+  </sitemap>
+  <sitemap>
+    <loc>http://www.example.com/sitemap2.xml</loc>
+  </sitemap>
+</sitemapindex>"#;
+
+    assert_eq!(Sitemap::parse(content),
+               Sitemap::Index(vec!["http://www.example.com/sitemap1.xml.gz".to_owned(),
+                                   "http://www.example.com/sitemap2.xml".to_owned()]));
+}
+
+#[test]
+fn test_garbage() {
+    assert_eq!(Sitemap::parse("<html><body>Not found</body></html>"),
+               Sitemap::UrlSet(Vec::new()));
+    assert_eq!(Sitemap::from_bytes(&[0x1f, 0x8b, 0x00, 0x01]),
+               Sitemap::UrlSet(Vec::new()));
+}
+
+#[test]
+fn test_limits() {
+    use self::flate2::write::GzEncoder;
+    use self::flate2::Compression;
+    use std::io::Write;
+
+    let mut content = String::from("<urlset>");
+    for i in 0..MAX_SITEMAP_ENTRIES + 10 {
+        content.push_str(&format!("<url><loc>http://example.com/{}</loc></url>", i));
+    }
+    content.push_str("</urlset>");
+    match Sitemap::parse(&content) {
+        Sitemap::UrlSet(entries) => assert_eq!(entries.len(), MAX_SITEMAP_ENTRIES),
+        _ => panic!("Expected urlset"),
+    }
+
+    // Highly compressible data is cut at the limit
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+    encoder.write_all(&[b' '; 100000]).unwrap();
+    let compressed = encoder.finish().unwrap();
+    assert_eq!(gunzip(&compressed, 1000).unwrap().len(), 1000);
+    assert_eq!(gunzip(&compressed, MAX_SITEMAP_BYTES).unwrap().len(), 100000);
+}
@@ -9,26 +9,118 @@ mod domain;
 mod db;
 mod crawler;
 mod log;
+mod politeness;
+mod migrations;
+mod store;
+mod sqlite;
 
 // Standard libs
 //use std::str;
+use std::env;
 use std::thread;
+use std::sync::Arc;
+use std::cell::RefCell;
+use std::time::Duration;
 
 //Extern libs
-use nix::unistd::{ fork, chdir, ForkResult };
+use nix::unistd::{ fork, chdir, getpid, gethostname, ForkResult };
 use nix::sys::stat::{ umask, Mode };
 use chan_signal::{ notify, Signal };
 
 
 // Project libs
 use log::{log_info, log_err, log_warn};
-use db::DBPool;
+use store::{ CrawlStore, MemoryStore, LiveClaims };
+use sqlite::SqliteStore;
+use crawler::{ CrawlConfig, PageOutcome, StopReason };
+use domain::domain_error::DomainError;
+use domain::robots_cache::{ RobotsCache, DEFAULT_TTL_SECS };
+use domain::discovered::{ DiscoveredDomains, normalize_domain };
 
+// Backend is picked by MOKOSZA_STORE: postgresql://..., sqlite:<path> or memory
+const STORE_VAR: &'static str = "MOKOSZA_STORE";
+const DB_URL: &'static str = "postgresql://mokosza:mokoszamokosza@\
+                              catdamnit.chs4hglw5opg.eu-west-1.rds.amazonaws.com\
+                              :5432/mokosza";
 const THREADS: usize = 10;
+// How long to wait before retrying domain with unavailable robots.txt
+const ROBOTS_RETRY_SECS: i64 = 3600;
+// How long to leave alone domain that kept failing
+const ERRORS_RETRY_SECS: i64 = 6 * 3600;
+// How long the feeder waits when there is nothing to claim
+const IDLE_SECS: u64 = 30;
+// Claimed domains go back to the queue if not extended in time
+const LEASE_SECS: i64 = 600;
+const HEARTBEAT_SECS: u64 = 60;
+// Crawl that has not moved on for this long is
+// considered hung and its lease is let go
+const STALE_SECS: u64 = 3600;
+// Domains whose claim expired or which were retried
+// this many times are given up on
+const MAX_ATTEMPTS: i32 = 3;
+// Discovered domains kept in memory before writing them out
+const DISCOVERED_FLUSH: usize = 1000;
+
+/*
+Identifies this instance in domain_list.claimed_by
+ */
+fn worker_id() -> String {
+    let mut buf = [0u8; 256];
+    let host = match gethostname(&mut buf) {
+        Ok(_) => {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        },
+        Err(_) => "unknown".to_owned(),
+    };
+    format!("{}:{}", host, getpid())
+}
+
+/*
+Opens the store described by spec and brings its schema
+up to date, the crawler must not run against schema it
+does not understand
+ */
+fn open_store(spec: &str, poolsize: u32) -> Result<Arc<CrawlStore>, String> {
+    if spec == "memory" {
+        return Ok(Arc::new(MemoryStore::new()));
+    }
+    if spec.starts_with("sqlite:") {
+        return SqliteStore::open(&spec["sqlite:".len()..])
+            .map(|store| Arc::new(store) as Arc<CrawlStore>)
+            .map_err(|err| err.to_string());
+    }
+
+    let pool = db::new_pool(spec, poolsize);
+    match migrations::migrate(&pool) {
+        Ok(version) => {
+            log_info(&format!("Schema is at version {}", version));
+            Ok(Arc::new(pool))
+        },
+        Err(err) => Err(err.to_string()),
+    }
+}
 
 fn main() {
     
     //let _ = crawler::crawl_domain("http://www.example.com");
+
+    let spec = env::var(STORE_VAR).unwrap_or(DB_URL.to_owned());
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // mokosza migrate, only update the schema and quit
+    if args.first().map_or(false, |arg| arg == "migrate") {
+        match open_store(&spec, 1) {
+            Ok(_) => {
+                println!("Schema is up to date");
+                ::std::process::exit(0);
+            },
+            Err(err) => {
+                println!("{}", err);
+                ::std::process::exit(1);
+            },
+        }
+    }
     
     // Daemonize the process
     match fork() {
@@ -52,31 +144,83 @@ fn main() {
                 ::std::process::exit(1);
             }
 
-            // Create DB connection pool
-            let pool = db::new_pool("postgresql://mokosza:mokoszamokosza@\
-                                  catdamnit.chs4hglw5opg.eu-west-1.rds.amazonaws.com\
-                                  :5432/mokosza", THREADS as u32);
+            // Create the store, for Postgres a connection pool
+            let store = match open_store(&spec, THREADS as u32) {
+                Ok(store) => store,
+                Err(err) => {
+                    log_err(&format!("Store is not usable: {}. Exiting", err));
+                    ::std::process::exit(1);
+                },
+            };
+
+            // Domains given on command line go to the queue
+            let seeds: Vec<(String, i32)> = args.iter()
+                .filter_map(|url| normalize_domain(url))
+                .map(|domain| (domain, 0))
+                .collect();
+            if let Err(err) = store.store_domains(&seeds) {
+                log_err(&format!("Failed to queue seed domains: {}", err));
+            }
+
+            // robots.txt cache shared by all threads
+            let robots_cache = Arc::new(
+                RobotsCache::with_store(Duration::from_secs(DEFAULT_TTL_SECS),
+                                        Box::new(store.clone())));
 
             // Register a signal handler
             let signal = notify(&[Signal::INT, Signal::KILL]);
             let (tx, rx) = chan::sync(THREADS);
+            let live = Arc::new(LiveClaims::new());
 
+            let worker = worker_id();
+            log_info(&format!("Claiming domains as {}", worker));
 
             // Create the crawler threads
             log_info("Spawning threads");
             for _ in 0..THREADS {
                 let rx = rx.clone();
-                let pool = pool.clone();
+                let store = store.clone();
+                let robots_cache = robots_cache.clone();
+                let live = live.clone();
+                let worker = worker.clone();
                 
                 thread::spawn(move || {
-                    run(rx, pool);
+                    run(rx, store, robots_cache, live, worker);
                 });
             }
 
+            // Keep our live claims and free the ones of dead workers
+            let heartbeat_store = store.clone();
+            let heartbeat_worker = worker.clone();
+            let heartbeat_live = live.clone();
+            thread::spawn(move || {
+                loop {
+                    for url in heartbeat_live.alive(Duration::from_secs(STALE_SECS)) {
+                        let _ = heartbeat_store.extend_lease(&url, &heartbeat_worker,
+                                                             LEASE_SECS);
+                    }
+                    if let Ok(reaped) = heartbeat_store.reap_expired(MAX_ATTEMPTS) {
+                        if reaped > 0 {
+                            log_warn(&format!("Returned {} expired claims to the queue", reaped));
+                        }
+                    }
+                    thread::sleep(Duration::from_secs(HEARTBEAT_SECS));
+                }
+            });
+
             // Create URL feeder thread
             thread::spawn(move || {
-                while let Some(url) = db::next_domain(&pool) {
-                    tx.send(url)
+                loop {
+                    // One round-trip for the whole batch
+                    let domains = store.claim_domains(&worker, THREADS, LEASE_SECS);
+                    if domains.is_empty() {
+                        thread::sleep(Duration::from_secs(IDLE_SECS));
+                        continue;
+                    }
+                    for url in domains {
+                        live.queued(&url);
+                        tx.send(url)
+                    }
                 }
             });
             
@@ -90,7 +234,24 @@ fn main() {
     }
 }
 
-fn run(rx: chan::Receiver<String>, pool: DBPool) {
+/*
+Writes out domains found so far
+ */
+fn flush_domains(store: &CrawlStore, discovered: &mut DiscoveredDomains) {
+    if discovered.is_empty() {
+        return;
+    }
+    let domains = discovered.drain();
+    match store.store_domains(&domains) {
+        Ok(_) => log_info(&format!("Stored {} discovered domains", domains.len())),
+        Err(err) => log_err(&format!("Failed to store {} discovered domains: {}",
+                                     domains.len(), err)),
+    }
+}
+
+fn run(rx: chan::Receiver<String>, store: Arc<CrawlStore>, robots_cache: Arc<RobotsCache>,
+       live: Arc<LiveClaims>, worker: String) {
+    let config = CrawlConfig::default();
     
     loop {
         log_info("Fetching new domain");
@@ -98,24 +259,70 @@ fn run(rx: chan::Receiver<String>, pool: DBPool) {
             None => break,
             Some(url) => {
                 log_info(&format!("Gonna crawl [{}]", url));
-                let crawl_result = crawler::crawl_domain(&url, |_, other| {
-                    // Here we can do something with the page
-                    // i.e. store it, send it etc.
-                    // Now jus print it
-                    //println!("{}",*page);
-                    db::store_domains(&pool, &other);
+                live.progress(&url);
+                let discovered = RefCell::new(DiscoveredDomains::new());
+                let crawl_result = crawler::crawl_domain(&url, &config, &robots_cache,
+                                                         Some(&store), |page_url, outcome| {
+                    live.progress(&url);
+                    match outcome {
+                        PageOutcome::Fetched(page, other) => {
+                            // Here page can be stored or sent for analysis
+                            // unless it asked not to be indexed
+                            if !page.may_index() {
+                                log_info(&format!("{} asked not to be indexed", page_url));
+                            }
+                            let mut discovered = discovered.borrow_mut();
+                            discovered.add_page(&other);
+                            if discovered.len() >= DISCOVERED_FLUSH {
+                                flush_domains(&*store, &mut discovered);
+                            }
+                        },
+                        // Analysed during the previous crawl already
+                        PageOutcome::Unchanged(_) => {},
+                        PageOutcome::Failed(err) => {
+                            log_warn(&format!("Broken link {}: {}", page_url, err));
+                        },
+                        PageOutcome::Skipped(_) => {},
+                    }
                 });
+                flush_domains(&*store, &mut discovered.borrow_mut());
                 
                 match crawl_result {
-                    Ok(_) => {
-                        let _ = db::domain_done(&pool, &url);
+                    Ok(stats) => {
+                        log_info(&format!("Crawled {} pages ({} bytes) of {} in {:?}. \
+                                           {} unchanged, {} failed, {} skipped. \
+                                           Rejected {} trap links, {} too deep. \
+                                           Breaker tripped {} times, \
+                                           final delay {:?}. Stopped: {}",
+                                          stats.pages, stats.bytes, url, stats.elapsed,
+                                          stats.unchanged, stats.failed, stats.skipped,
+                                          stats.traps_rejected, stats.depth_truncated,
+                                          stats.breaker_trips,
+                                          stats.delay, stats.stop_reason.as_str()));
+                        match stats.stop_reason {
+                            // Host in trouble is not crawled, try again later
+                            StopReason::TooManyErrors => {
+                                let _ = store.domain_retry(&url, &worker, ERRORS_RETRY_SECS,
+                                                         MAX_ATTEMPTS);
+                            },
+                            _ => {
+                                let _ = store.domain_done(&url, &worker, stats.stop_reason.as_str());
+                            },
+                        }
+                    },
+                    Err(DomainError::RobotsUnavailable) => {
+                        // Unresolvable or dead hosts end up here too, so not forever
+                        log_warn(&format!("robots.txt for {} unavailable. Will retry later",
+                                          url));
+                        let _ = store.domain_retry(&url, &worker, ROBOTS_RETRY_SECS, MAX_ATTEMPTS);
                     },
                     Err(err) => {
                         log_err(&format!("Failed to crawl {}. error: {}",
                                          url, err));
-                        let _ = db::domain_err(&pool, &url);
+                        let _ = store.domain_err(&url, &worker);
                     }
                 }
+                live.finished(&url);
             }
         }
     }
@@ -0,0 +1,144 @@
+// Crates
+
+// Modules
+
+// Standard libs
+use std::fmt;
+
+// Project libs
+use log::*;
+use db::DBPool;
+
+/*
+Schema change shipped with the binary
+ */
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/*
+Append only, already released migrations are never edited
+ */
+pub const MIGRATIONS: &'static [Migration] = &[
+    Migration { version: 1, name: "domain_list",
+                sql: include_str!("../migrations/0001_domain_list.sql") },
+    Migration { version: 2, name: "domain_claims",
+                sql: include_str!("../migrations/0002_domain_claims.sql") },
+    Migration { version: 3, name: "robots_cache",
+                sql: include_str!("../migrations/0003_robots_cache.sql") },
+    Migration { version: 4, name: "pages",
+                sql: include_str!("../migrations/0004_pages.sql") },
+];
+
+/*
+Schema of the single node SQLite store, versioned by PRAGMA user_version
+ */
+pub const SQLITE_MIGRATIONS: &'static [Migration] = &[
+    Migration { version: 1, name: "initial",
+                sql: include_str!("../migrations/sqlite/0001_initial.sql") },
+];
+
+// Serializes instances migrating at the same time
+const MIGRATION_LOCK: i64 = 0x6d6f6b6f737a61;
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationError {
+    // Database was migrated by newer binary
+    SchemaTooNew(i32, i32),
+    DBError(String),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MigrationError::SchemaTooNew(found, known) => {
+                write!(f, "Schema version {} is newer than {} supported by this binary",
+                       found, known)
+            },
+            MigrationError::DBError(ref err) => write!(f, "Migration failed: {}", err),
+        }
+    }
+}
+
+/*
+Newest schema version this binary knows
+ */
+pub fn latest_version(migrations: &[Migration]) -> i32 {
+    migrations.last().map_or(0, |m| m.version)
+}
+
+/*
+Migrations still to be applied on top of current version
+ */
+pub fn pending(migrations: &'static [Migration], current: i32)
+               -> Result<&'static [Migration], MigrationError> {
+    let latest = latest_version(migrations);
+    if current > latest {
+        return Err(MigrationError::SchemaTooNew(current, latest));
+    }
+    let idx = migrations.iter().position(|m| m.version > current).unwrap_or(migrations.len());
+    Ok(&migrations[idx..])
+}
+
+/*
+Brings the schema up to date in single transaction.
+Returns the schema version in use.
+ */
+pub fn migrate(pool: &DBPool) -> Result<i32, MigrationError> {
+    let db_err = |err: &fmt::Display| MigrationError::DBError(err.to_string());
+
+    let conn = try!(pool.0.get().map_err(|err| db_err(&err)));
+    let tx = try!(conn.transaction().map_err(|err| db_err(&err)));
+    // Lock first, concurrent CREATE TABLE IF NOT EXISTS can still collide
+    try!(tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+         .map_err(|err| db_err(&err)));
+    try!(tx.batch_execute("CREATE TABLE IF NOT EXISTS schema_version (\
+                           version     INTEGER PRIMARY KEY,\
+                           name        VARCHAR NOT NULL,\
+                           applied_at  TIMESTAMPTZ NOT NULL DEFAULT NOW())")
+         .map_err(|err| db_err(&err)));
+
+    let current: i32 = {
+        let rows = try!(tx.query("SELECT COALESCE(MAX(version), 0) FROM schema_version", &[])
+                        .map_err(|err| db_err(&err)));
+        rows.into_iter().next().map_or(0, |row| row.get(0))
+    };
+
+    let todo = try!(pending(MIGRATIONS, current));
+    for migration in todo {
+        log_info(&format!("Applying migration {} {}", migration.version, migration.name));
+        try!(tx.batch_execute(migration.sql).map_err(|err| db_err(&err)));
+        try!(tx.execute("INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+                        &[&migration.version, &migration.name])
+             .map_err(|err| db_err(&err)));
+    }
+    try!(tx.commit().map_err(|err| db_err(&err)));
+
+    Ok(todo.last().map_or(current, |m| m.version))
+}
+
+#[cfg(test)]
+#[test]
+fn test_migrations_ordered() {
+    for migrations in &[MIGRATIONS, SQLITE_MIGRATIONS] {
+        let mut previous = 0;
+        for migration in migrations.iter() {
+            assert_eq!(migration.version, previous + 1);
+            assert!(!migration.sql.trim().is_empty());
+            previous = migration.version;
+        }
+        assert_eq!(latest_version(migrations), previous);
+    }
+}
+
+#[test]
+fn test_pending() {
+    let latest = latest_version(MIGRATIONS);
+    assert_eq!(pending(MIGRATIONS, 0).unwrap().len(), MIGRATIONS.len());
+    assert_eq!(pending(MIGRATIONS, 1).unwrap()[0].version, 2);
+    assert!(pending(MIGRATIONS, latest).unwrap().is_empty());
+    assert_eq!(pending(MIGRATIONS, latest + 1).err(),
+               Some(MigrationError::SchemaTooNew(latest + 1, latest)));
+}
@@ -0,0 +1,251 @@
+// Crates
+
+// Modules
+
+// Standard libs
+use std::cmp;
+use std::time::{ Duration, Instant };
+
+// Project libs
+use log::*;
+
+/*
+How the delay between requests follows server's health
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct PolitenessConfig {
+    // Wait at least this many times the last response time
+    pub latency_factor: u32,
+    // Healthy server gets the delay cut by this percent per request
+    pub recovery_percent: u32,
+    // Failures in a row which open the circuit breaker
+    pub breaker_failures: usize,
+    // How long the host is left alone once the breaker opens
+    pub breaker_cooldown: Duration,
+}
+
+impl Default for PolitenessConfig {
+    fn default() -> PolitenessConfig {
+        PolitenessConfig {
+            latency_factor: 10,
+            recovery_percent: 10,
+            breaker_failures: 5,
+            breaker_cooldown: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    // Requests go through
+    Closed,
+    // Host is paused till the cooldown passes
+    Open,
+    // Cooldown passed, next request decides
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half-open",
+        }
+    }
+}
+
+/*
+Delay and circuit breaker of single host
+ */
+pub struct HostPoliteness {
+    host: String,
+    config: PolitenessConfig,
+    delay: Duration,
+    // Never go below what robots.txt asked for
+    floor: Duration,
+    ceiling: Duration,
+    failures: usize,
+    open_until: Option<Instant>,
+    // Times the breaker opened
+    pub trips: usize,
+}
+
+impl HostPoliteness {
+    pub fn new(host: &str, config: PolitenessConfig, initial: Duration,
+               floor: Duration, ceiling: Duration) -> HostPoliteness {
+        HostPoliteness {
+            host: host.to_owned(),
+            config: config,
+            delay: cmp::min(cmp::max(initial, floor), ceiling),
+            floor: floor,
+            ceiling: ceiling,
+            failures: 0,
+            open_until: None,
+            trips: 0,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn state(&self) -> BreakerState {
+        match self.open_until {
+            None => BreakerState::Closed,
+            Some(until) if Instant::now() < until => BreakerState::Open,
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    /*
+    How long to wait before the next request
+     */
+    pub fn wait_time(&self) -> Duration {
+        match self.open_until {
+            Some(until) => {
+                let now = Instant::now();
+                let cooldown = if until > now { until - now } else { Duration::from_secs(0) };
+                cmp::max(cooldown, self.delay)
+            },
+            None => self.delay,
+        }
+    }
+
+    /*
+    Slow responses push the delay up, fast ones let
+    it drop slowly back to the floor
+     */
+    pub fn on_success(&mut self, response_time: Duration) {
+        if self.open_until.is_some() {
+            self.open_until = None;
+            log_info(&format!("Circuit breaker for {} is {}",
+                              self.host, self.state().as_str()));
+        }
+        self.failures = 0;
+
+        let target = cmp::max(self.floor, response_time * self.config.latency_factor);
+        let delay = if target > self.delay {
+            target
+        } else {
+            let recovered = self.delay - self.delay * self.config.recovery_percent / 100;
+            cmp::max(target, recovered)
+        };
+        self.set_delay(delay);
+    }
+
+    /*
+    Any failed request, opens the breaker once too many
+    of them come in a row or the probe after cooldown fails
+     */
+    pub fn on_failure(&mut self) {
+        let delay = self.delay * 2;
+        self.set_delay(delay);
+        self.count_failure();
+    }
+
+    /*
+    Server told us to slow down (429/503), possibly saying for how long.
+    Counts as failure too so host that keeps refusing us gets paused.
+     */
+    pub fn on_overload(&mut self, retry_after: Option<Duration>) {
+        let delay = cmp::max(self.delay * 2, retry_after.unwrap_or(Duration::from_secs(0)));
+        self.set_delay(delay);
+        self.count_failure();
+    }
+
+    fn count_failure(&mut self) {
+        self.failures += 1;
+        let probe_failed = self.state() == BreakerState::HalfOpen;
+        if probe_failed || self.failures >= self.config.breaker_failures {
+            self.open_until = Some(Instant::now() + self.config.breaker_cooldown);
+            self.failures = 0;
+            self.trips += 1;
+            log_warn(&format!("Circuit breaker for {} is {} for {:?}",
+                              self.host, self.state().as_str(), self.config.breaker_cooldown));
+        }
+    }
+
+    fn set_delay(&mut self, delay: Duration) {
+        let delay = cmp::min(cmp::max(delay, self.floor), self.ceiling);
+        if delay != self.delay {
+            log_info(&format!("Delay for {} changed from {:?} to {:?}",
+                              self.host, self.delay, delay));
+            self.delay = delay;
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_adaptive_delay() {
+    let second = Duration::from_secs(1);
+    let mut host = HostPoliteness::new("example.com", PolitenessConfig::default(),
+                                       second * 5, second, second * 60);
+    assert_eq!(host.delay(), second * 5);
+
+    // Slow server, 10x its response time
+    host.on_success(Duration::from_millis(2000));
+    assert_eq!(host.delay(), second * 20);
+
+    // Fast responses bring it down by 10% each
+    host.on_success(Duration::from_millis(10));
+    assert_eq!(host.delay(), second * 18);
+    for _ in 0..100 {
+        host.on_success(Duration::from_millis(10));
+    }
+    assert_eq!(host.delay(), second);
+
+    host.on_overload(Some(second * 30));
+    assert_eq!(host.delay(), second * 30);
+    host.on_overload(None);
+    assert_eq!(host.delay(), second * 60);
+}
+
+#[test]
+fn test_circuit_breaker() {
+    let config = PolitenessConfig {
+        breaker_failures: 3,
+        breaker_cooldown: Duration::from_secs(0),
+        .. PolitenessConfig::default()
+    };
+    let second = Duration::from_secs(1);
+    let mut host = HostPoliteness::new("example.com", config, second, second, second * 60);
+
+    host.on_failure();
+    host.on_failure();
+    assert_eq!(host.state(), BreakerState::Closed);
+    assert_eq!(host.delay(), second * 4);
+    host.on_failure();
+    assert_eq!(host.trips, 1);
+    // Cooldown of zero passes right away
+    assert_eq!(host.state(), BreakerState::HalfOpen);
+
+    // Failed probe opens it again straight away
+    host.on_failure();
+    assert_eq!(host.trips, 2);
+
+    host.on_success(Duration::from_millis(10));
+    assert_eq!(host.state(), BreakerState::Closed);
+
+    let long = PolitenessConfig { breaker_failures: 1, .. PolitenessConfig::default() };
+    let mut host = HostPoliteness::new("example.com", long, second, second, second * 60);
+    host.on_failure();
+    assert_eq!(host.state(), BreakerState::Open);
+    assert!(host.wait_time() > second * 250);
+}
+
+#[test]
+fn test_overload_opens_breaker() {
+    let config = PolitenessConfig { breaker_failures: 3, .. PolitenessConfig::default() };
+    let second = Duration::from_secs(1);
+    let mut host = HostPoliteness::new("example.com", config, second, second, second * 60);
+
+    // 503 after 503
+    host.on_overload(None);
+    host.on_overload(None);
+    assert_eq!(host.state(), BreakerState::Closed);
+    host.on_overload(None);
+    assert_eq!(host.trips, 1);
+    assert_eq!(host.state(), BreakerState::Open);
+}
@@ -0,0 +1,312 @@
+// Crates
+extern crate rusqlite;
+extern crate time;
+
+// Modules
+
+// Standard libs
+use std::sync::{ Mutex, MutexGuard };
+
+// Project libs
+use self::rusqlite::Connection;
+use self::rusqlite::types::ToSql;
+use domain::robots_cache::{ RobotsStore, CachedRobots };
+use domain::page_store::{ PageStore, PageRecord };
+use migrations::{ self, MigrationError, SQLITE_MIGRATIONS };
+use store::{ CrawlStore, StoreError };
+use log::*;
+
+/*
+Store in single SQLite file for single node deployments.
+One connection shared by all threads.
+ */
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+fn sql_err(err: rusqlite::Error) -> StoreError {
+    StoreError(err.to_string())
+}
+
+impl SqliteStore {
+    /*
+    Opens the database file, creating it and its schema if needed.
+    Path :memory: keeps the database in memory.
+     */
+    pub fn open(path: &str) -> Result<SqliteStore, MigrationError> {
+        let conn = try!(Connection::open(path)
+                        .map_err(|err| MigrationError::DBError(err.to_string())));
+        let store = SqliteStore { conn: Mutex::new(conn) };
+        try!(store.migrate());
+        Ok(store)
+    }
+
+    /*
+    Applies pending schema changes, version is kept in PRAGMA user_version
+     */
+    fn migrate(&self) -> Result<i32, MigrationError> {
+        let db_err = |err: rusqlite::Error| MigrationError::DBError(err.to_string());
+        let conn = try!(self.lock().map_err(|err| MigrationError::DBError(err.0)));
+
+        let current: i32 = try!(conn.query_row("PRAGMA user_version", &[], |row| row.get(0))
+                                .map_err(&db_err));
+        let todo = try!(migrations::pending(SQLITE_MIGRATIONS, current));
+        for migration in todo {
+            log_info(&format!("Applying SQLite migration {} {}",
+                              migration.version, migration.name));
+            let sql = format!("BEGIN; {} PRAGMA user_version = {}; COMMIT;",
+                              migration.sql, migration.version);
+            if let Err(err) = conn.execute_batch(&sql) {
+                let _ = conn.execute_batch("ROLLBACK");
+                return Err(db_err(err));
+            }
+        }
+        Ok(todo.last().map_or(current, |m| m.version))
+    }
+
+    fn lock(&self) -> Result<MutexGuard<Connection>, StoreError> {
+        self.conn.lock().map_err(|_| StoreError("SQLite connection lock poisoned".to_owned()))
+    }
+
+    fn execute(&self, query: &str, params: &[&ToSql]) -> Result<u64, StoreError> {
+        let conn = try!(self.lock());
+        conn.execute(query, params).map(|n| n as u64).map_err(sql_err)
+    }
+
+    /*
+    Runs f in write transaction, other processes using
+    the file wait till it is committed
+     */
+    fn transaction<T, F>(&self, f: F) -> Result<T, StoreError>
+        where F: FnOnce(&Connection) -> Result<T, rusqlite::Error>
+    {
+        let conn = try!(self.lock());
+        try!(conn.execute_batch("BEGIN IMMEDIATE").map_err(sql_err));
+        match f(&conn) {
+            Ok(res) => {
+                try!(conn.execute_batch("COMMIT").map_err(sql_err));
+                Ok(res)
+            },
+            Err(err) => {
+                let _ = conn.execute_batch("ROLLBACK");
+                Err(sql_err(err))
+            },
+        }
+    }
+}
+
+impl RobotsStore for SqliteStore {
+    fn load_robots(&self, key: &str) -> Option<CachedRobots> {
+        let q = "SELECT status, content, fetched_at FROM robots_cache WHERE origin=?1";
+
+        self.lock().ok().and_then(|conn| {
+            conn.query_row(q, &[&key], |row| {
+                let status: i64 = row.get(0);
+                CachedRobots {
+                    status: status as u32,
+                    content: row.get(1),
+                    fetched_at: row.get(2),
+                }
+            }).ok()
+        })
+    }
+
+    fn save_robots(&self, key: &str, robots: &CachedRobots) {
+        let q = "INSERT OR REPLACE INTO robots_cache (origin, status, content, fetched_at) \
+                 VALUES (?1, ?2, ?3, ?4)";
+        let status = robots.status as i64;
+        let _ = self.execute(q, &[&key, &status, &robots.content, &robots.fetched_at]);
+    }
+}
+
+impl PageStore for SqliteStore {
+    fn load_page(&self, url: &str) -> Option<PageRecord> {
+        let q = "SELECT etag, last_modified, content_hash, outlinks, fetched_at \
+                 FROM pages WHERE url=?1";
+
+        self.lock().ok().and_then(|conn| {
+            conn.query_row(q, &[&url], |row| {
+                let content_hash: i64 = row.get(2);
+                let outlinks: String = row.get(3);
+                PageRecord {
+                    url: url.to_owned(),
+                    etag: row.get(0),
+                    last_modified: row.get(1),
+                    content_hash: content_hash as u64,
+                    // One link per line
+                    outlinks: outlinks.lines().map(|l| l.to_owned()).collect(),
+                    fetched_at: row.get(4),
+                }
+            }).ok()
+        })
+    }
+
+    fn save_page(&self, record: &PageRecord) {
+        let q = "INSERT OR REPLACE INTO pages \
+                 (url, etag, last_modified, content_hash, outlinks, fetched_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        let content_hash = record.content_hash as i64;
+        let outlinks = record.outlinks.join("\n");
+        let _ = self.execute(q, &[&record.url, &record.etag, &record.last_modified,
+                                  &content_hash, &outlinks, &record.fetched_at]);
+    }
+}
+
+impl CrawlStore for SqliteStore {
+    fn claim_domains(&self, worker: &str, limit: usize, lease_secs: i64) -> Vec<String> {
+        let now = time::get_time().sec;
+        let limit = limit as i64;
+        let lease_until = now + lease_secs;
+
+        let claimed = self.transaction(|conn| {
+            let urls: Vec<String> = {
+                let mut stmt = try!(conn.prepare(
+                    "SELECT domain_url FROM domain_list WHERE crawled_at IS NULL \
+                     AND status='new' AND (retry_at IS NULL OR retry_at < ?1) \
+                     ORDER BY domain_url LIMIT ?2"));
+                let rows = try!(stmt.query_map(&[&now, &limit], |row| row.get(0)));
+                try!(rows.collect())
+            };
+            for url in &urls {
+                try!(conn.execute("UPDATE domain_list SET status='processing', claimed_by=?2, \
+                                   claimed_at=?3, lease_until=?4 WHERE domain_url=?1",
+                                  &[url, &worker, &now, &lease_until]));
+            }
+            Ok(urls)
+        });
+        claimed.unwrap_or(Vec::new())
+    }
+
+    fn extend_lease(&self, url: &str, worker: &str, lease_secs: i64) -> Result<u64, StoreError> {
+        let lease_until = time::get_time().sec + lease_secs;
+        self.execute("UPDATE domain_list SET lease_until=?3 \
+                      WHERE domain_url=?1 AND claimed_by=?2 AND status='processing'",
+                     &[&url, &worker, &lease_until])
+    }
+
+    fn reap_expired(&self, max_attempts: i32) -> Result<u64, StoreError> {
+        let now = time::get_time().sec;
+        self.execute("UPDATE domain_list SET attempts=attempts + 1, \
+                      status=CASE WHEN attempts + 1 >= ?1 THEN 'failed' ELSE 'new' END, \
+                      claimed_by=NULL, lease_until=NULL \
+                      WHERE status='processing' AND lease_until < ?2",
+                     &[&max_attempts, &now])
+    }
+
+    fn domain_done(&self, url: &str, worker: &str, stop_reason: &str) -> Result<(), StoreError> {
+        let now = time::get_time().sec;
+        self.execute("UPDATE domain_list SET status='done', crawled_at=?3, stop_reason=?4, \
+                      claimed_by=NULL, lease_until=NULL WHERE domain_url=?1 AND claimed_by=?2",
+                     &[&url, &worker, &now, &stop_reason])
+            .map(|_| ())
+    }
+
+    fn domain_retry(&self, url: &str, worker: &str, delay_secs: i64, max_attempts: i32)
+                    -> Result<(), StoreError> {
+        let retry_at = time::get_time().sec + delay_secs;
+        self.execute("UPDATE domain_list SET attempts=attempts + 1, \
+                      status=CASE WHEN attempts + 1 >= ?4 THEN 'failed' ELSE 'new' END, \
+                      retry_at=?3, claimed_by=NULL, lease_until=NULL \
+                      WHERE domain_url=?1 AND claimed_by=?2",
+                     &[&url, &worker, &retry_at, &max_attempts])
+            .map(|_| ())
+    }
+
+    fn domain_err(&self, url: &str, worker: &str) -> Result<(), StoreError> {
+        let now = time::get_time().sec;
+        self.execute("UPDATE domain_list SET status='invalid', crawled_at=?3, \
+                      claimed_by=NULL, lease_until=NULL WHERE domain_url=?1 AND claimed_by=?2",
+                     &[&url, &worker, &now])
+            .map(|_| ())
+    }
+
+    fn store_domains(&self, list: &[(String, i32)]) -> Result<u64, StoreError> {
+        let now = time::get_time().sec;
+        self.transaction(|conn| {
+            let mut modified = 0;
+            for &(ref url, ref count) in list {
+                try!(conn.execute("INSERT OR IGNORE INTO domain_list \
+                                   (domain_url, first_seen, last_seen) VALUES (?1, ?2, ?2)",
+                                  &[url, &now]));
+                modified += try!(conn.execute("UPDATE domain_list SET \
+                                               times_seen=times_seen + ?2, last_seen=?3 \
+                                               WHERE domain_url=?1",
+                                              &[url, count, &now])) as u64;
+            }
+            Ok(modified)
+        })
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_sqlite_store() {
+    let store = SqliteStore::open(":memory:").unwrap();
+    // Reopening the schema is a no-op
+    assert_eq!(store.migrate().unwrap(), 1);
+
+    let domains = vec![("http://a.com".to_owned(), 1), ("http://b.com".to_owned(), 2)];
+    assert_eq!(store.store_domains(&domains).unwrap(), 2);
+    assert_eq!(store.store_domains(&domains).unwrap(), 2);
+
+    assert_eq!(store.claim_domains("w1", 1, -1), vec!["http://a.com".to_owned()]);
+    assert_eq!(store.claim_domains("w2", 5, 600), vec!["http://b.com".to_owned()]);
+    assert!(store.claim_domains("w2", 5, 600).is_empty());
+
+    // a.com lease is already gone
+    assert_eq!(store.reap_expired(3).unwrap(), 1);
+    assert_eq!(store.claim_domains("w1", 5, 600), vec!["http://a.com".to_owned()]);
+    assert_eq!(store.extend_lease("http://a.com", "w1", 600).unwrap(), 1);
+    // Lease of domain held by other worker is not ours to extend
+    assert_eq!(store.extend_lease("http://b.com", "w1", 600).unwrap(), 0);
+
+    assert!(store.domain_done("http://a.com", "w1", "completed").is_ok());
+    assert!(store.domain_retry("http://b.com", "w2", 3600, 3).is_ok());
+    assert!(store.claim_domains("w1", 5, 600).is_empty());
+
+    let record = PageRecord {
+        url: "http://a.com/".to_owned(),
+        etag: Some("\"x\"".to_owned()),
+        last_modified: None,
+        content_hash: u64::max_value(),
+        outlinks: vec!["http://a.com/b".to_owned(), "http://a.com/c".to_owned()],
+        fetched_at: 10,
+    };
+    store.save_page(&record);
+    assert_eq!(store.load_page(&record.url), Some(record));
+
+    let robots = CachedRobots { status: 200, content: "User-agent: *".to_owned(), fetched_at: 1 };
+    store.save_robots("http://a.com", &robots);
+    assert_eq!(store.load_robots("http://a.com"), Some(robots));
+}
+
+#[test]
+fn test_sqlite_queue() {
+    let store = SqliteStore::open(":memory:").unwrap();
+    let row = |url: &str| -> (String, Option<String>, Option<String>, i64) {
+        let conn = store.lock().unwrap();
+        conn.query_row("SELECT status, stop_reason, claimed_by, times_seen \
+                        FROM domain_list WHERE domain_url=?1", &[&url],
+                       |row| (row.get(0), row.get(1), row.get(2), row.get(3))).unwrap()
+    };
+
+    let _ = store.store_domains(&[("http://a.com".to_owned(), 1), ("http://b.com".to_owned(), 2)]);
+    let _ = store.store_domains(&[("http://b.com".to_owned(), 3)]);
+    assert_eq!(row("http://b.com").3, 5);
+
+    assert_eq!(store.claim_domains("w1", 2, 600).len(), 2);
+    assert_eq!(row("http://a.com").2, Some("w1".to_owned()));
+    // Not ours to finish
+    let _ = store.domain_done("http://a.com", "w2", "completed");
+    assert_eq!(row("http://a.com").0, "processing");
+    let _ = store.domain_done("http://a.com", "w1", "completed");
+    assert_eq!(row("http://a.com"), ("done".to_owned(), Some("completed".to_owned()), None, 1));
+
+    // Retried domain is released and given up on after max_attempts
+    let _ = store.domain_retry("http://b.com", "w1", -1, 2);
+    assert_eq!(row("http://b.com").2, None);
+    assert_eq!(store.claim_domains("w1", 2, 600), vec!["http://b.com".to_owned()]);
+    let _ = store.domain_retry("http://b.com", "w1", -1, 2);
+    assert_eq!(row("http://b.com").0, "failed");
+    assert!(store.claim_domains("w1", 2, 600).is_empty());
+}
@@ -0,0 +1,414 @@
+// Crates
+extern crate time;
+
+// Modules
+
+// Standard libs
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+// Project libs
+use domain::robots_cache::{ RobotsStore, CachedRobots };
+use domain::page_store::{ PageStore, PageRecord };
+
+#[derive(Debug)]
+pub struct StoreError(pub String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Store error: {}", self.0)
+    }
+}
+
+/*
+Everything the crawler persists. Domain queue lives here,
+pages with their links and robots.txt come from the
+supertraits so the same store feeds RobotsCache and crawl_domain.
+ */
+pub trait CrawlStore: PageStore + RobotsStore {
+    // Hands out up to limit new domains leased to the worker
+    fn claim_domains(&self, worker: &str, limit: usize, lease_secs: i64) -> Vec<String>;
+    // Pushes the lease of the domain if the worker still holds it
+    fn extend_lease(&self, url: &str, worker: &str, lease_secs: i64) -> Result<u64, StoreError>;
+    // Requeues expired claims, fails them after max_attempts
+    fn reap_expired(&self, max_attempts: i32) -> Result<u64, StoreError>;
+    // Releasing the domain below only works for the worker still holding it
+    fn domain_done(&self, url: &str, worker: &str, stop_reason: &str) -> Result<(), StoreError>;
+    // Requeues the domain for later, fails it after max_attempts
+    fn domain_retry(&self, url: &str, worker: &str, delay_secs: i64, max_attempts: i32)
+                    -> Result<(), StoreError>;
+    fn domain_err(&self, url: &str, worker: &str) -> Result<(), StoreError>;
+    // Upserts discovered domains with times seen, list must not repeat a domain
+    fn store_domains(&self, list: &[(String, i32)]) -> Result<u64, StoreError>;
+}
+
+/*
+Shared store can be handed to RobotsCache and crawl_domain as is
+ */
+impl RobotsStore for Arc<CrawlStore> {
+    fn load_robots(&self, key: &str) -> Option<CachedRobots> {
+        (**self).load_robots(key)
+    }
+
+    fn save_robots(&self, key: &str, robots: &CachedRobots) {
+        (**self).save_robots(key, robots)
+    }
+}
+
+impl PageStore for Arc<CrawlStore> {
+    fn load_page(&self, url: &str) -> Option<PageRecord> {
+        (**self).load_page(url)
+    }
+
+    fn save_page(&self, record: &PageRecord) {
+        (**self).save_page(record)
+    }
+}
+
+/*
+Row of domain_list kept by MemoryStore, times are unix seconds
+ */
+#[derive(Debug, Clone)]
+struct DomainRow {
+    status: &'static str,
+    crawled_at: Option<i64>,
+    retry_at: Option<i64>,
+    claimed_by: Option<String>,
+    lease_until: Option<i64>,
+    attempts: i32,
+    // Kept same as the database backends do, only tests look at them
+    #[cfg_attr(not(test), allow(dead_code))]
+    stop_reason: Option<String>,
+    #[cfg_attr(not(test), allow(dead_code))]
+    times_seen: i32,
+}
+
+struct MemoryState {
+    // Ordered so domains are claimed in predictable order
+    domains: BTreeMap<String, DomainRow>,
+    pages: HashMap<String, PageRecord>,
+    robots: HashMap<String, CachedRobots>,
+}
+
+/*
+Keeps everything in memory, for tests and local runs.
+Nothing survives the process.
+ */
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            state: Mutex::new(MemoryState {
+                domains: BTreeMap::new(),
+                pages: HashMap::new(),
+                robots: HashMap::new(),
+            }),
+        }
+    }
+
+    /*
+    Updates and releases the domain if the worker holds it
+     */
+    fn release_domain<F>(&self, url: &str, worker: &str, update: F) -> Result<(), StoreError>
+        where F: FnOnce(&mut DomainRow)
+    {
+        let mut state = try!(self.state.lock().map_err(|_| poisoned()));
+        if let Some(row) = state.domains.get_mut(url) {
+            if row.claimed_by.as_ref().map_or(false, |w| w == worker) {
+                update(row);
+                row.claimed_by = None;
+                row.lease_until = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn poisoned() -> StoreError {
+    StoreError("memory store lock poisoned".to_owned())
+}
+
+impl RobotsStore for MemoryStore {
+    fn load_robots(&self, key: &str) -> Option<CachedRobots> {
+        self.state.lock().ok().and_then(|state| state.robots.get(key).cloned())
+    }
+
+    fn save_robots(&self, key: &str, robots: &CachedRobots) {
+        if let Ok(mut state) = self.state.lock() {
+            state.robots.insert(key.to_owned(), robots.clone());
+        }
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn load_page(&self, url: &str) -> Option<PageRecord> {
+        self.state.lock().ok().and_then(|state| state.pages.get(url).cloned())
+    }
+
+    fn save_page(&self, record: &PageRecord) {
+        if let Ok(mut state) = self.state.lock() {
+            state.pages.insert(record.url.clone(), record.clone());
+        }
+    }
+}
+
+impl CrawlStore for MemoryStore {
+    fn claim_domains(&self, worker: &str, limit: usize, lease_secs: i64) -> Vec<String> {
+        let now = time::get_time().sec;
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut claimed = Vec::new();
+        for (url, row) in state.domains.iter_mut() {
+            if claimed.len() >= limit {
+                break;
+            }
+            let ready = row.retry_at.map_or(true, |at| at < now);
+            if row.status == "new" && row.crawled_at.is_none() && ready {
+                row.status = "processing";
+                row.claimed_by = Some(worker.to_owned());
+                row.lease_until = Some(now + lease_secs);
+                claimed.push(url.clone());
+            }
+        }
+        claimed
+    }
+
+    fn extend_lease(&self, url: &str, worker: &str, lease_secs: i64) -> Result<u64, StoreError> {
+        let now = time::get_time().sec;
+        let mut state = try!(self.state.lock().map_err(|_| poisoned()));
+        match state.domains.get_mut(url) {
+            Some(row) if row.status == "processing"
+                && row.claimed_by.as_ref().map_or(false, |w| w == worker) => {
+                row.lease_until = Some(now + lease_secs);
+                Ok(1)
+            },
+            _ => Ok(0),
+        }
+    }
+
+    fn reap_expired(&self, max_attempts: i32) -> Result<u64, StoreError> {
+        let now = time::get_time().sec;
+        let mut state = try!(self.state.lock().map_err(|_| poisoned()));
+        let mut modified = 0;
+        for row in state.domains.values_mut() {
+            if row.status == "processing" && row.lease_until.map_or(false, |until| until < now) {
+                row.attempts += 1;
+                row.status = if row.attempts >= max_attempts { "failed" } else { "new" };
+                row.claimed_by = None;
+                row.lease_until = None;
+                modified += 1;
+            }
+        }
+        Ok(modified)
+    }
+
+    fn domain_done(&self, url: &str, worker: &str, stop_reason: &str) -> Result<(), StoreError> {
+        let now = time::get_time().sec;
+        self.release_domain(url, worker, |row| {
+            row.status = "done";
+            row.crawled_at = Some(now);
+            row.stop_reason = Some(stop_reason.to_owned());
+        })
+    }
+
+    fn domain_retry(&self, url: &str, worker: &str, delay_secs: i64, max_attempts: i32)
+                    -> Result<(), StoreError> {
+        let now = time::get_time().sec;
+        self.release_domain(url, worker, |row| {
+            row.attempts += 1;
+            row.status = if row.attempts >= max_attempts { "failed" } else { "new" };
+            row.retry_at = Some(now + delay_secs);
+        })
+    }
+
+    fn domain_err(&self, url: &str, worker: &str) -> Result<(), StoreError> {
+        let now = time::get_time().sec;
+        self.release_domain(url, worker, |row| {
+            row.status = "invalid";
+            row.crawled_at = Some(now);
+        })
+    }
+
+    fn store_domains(&self, list: &[(String, i32)]) -> Result<u64, StoreError> {
+        let mut state = try!(self.state.lock().map_err(|_| poisoned()));
+        for &(ref url, count) in list {
+            let row = state.domains.entry(url.clone()).or_insert(DomainRow {
+                status: "new",
+                crawled_at: None,
+                retry_at: None,
+                claimed_by: None,
+                lease_until: None,
+                attempts: 0,
+                stop_reason: None,
+                times_seen: 0,
+            });
+            row.times_seen += count;
+        }
+        Ok(list.len() as u64)
+    }
+}
+
+/*
+Domains claimed by this process with the time their crawl last
+moved on, None while still waiting for a thread. Heartbeat extends
+only these, so domain of a hung thread expires and gets reaped.
+ */
+pub struct LiveClaims {
+    claims: Mutex<HashMap<String, Option<Instant>>>,
+}
+
+impl LiveClaims {
+    pub fn new() -> LiveClaims {
+        LiveClaims {
+            claims: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn queued(&self, url: &str) {
+        if let Ok(mut claims) = self.claims.lock() {
+            claims.insert(url.to_owned(), None);
+        }
+    }
+
+    pub fn progress(&self, url: &str) {
+        if let Ok(mut claims) = self.claims.lock() {
+            claims.insert(url.to_owned(), Some(Instant::now()));
+        }
+    }
+
+    pub fn finished(&self, url: &str) {
+        if let Ok(mut claims) = self.claims.lock() {
+            claims.remove(url);
+        }
+    }
+
+    /*
+    Claims worth extending, waiting ones and those
+    which made progress within max_idle
+     */
+    pub fn alive(&self, max_idle: Duration) -> Vec<String> {
+        match self.claims.lock() {
+            Ok(claims) => {
+                claims.iter()
+                    .filter(|&(_, last)| last.map_or(true, |at| at.elapsed() < max_idle))
+                    .map(|(url, _)| url.clone())
+                    .collect()
+            },
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn status_of(store: &MemoryStore, url: &str) -> Option<&'static str> {
+    store.state.lock().unwrap().domains.get(url).map(|row| row.status)
+}
+
+#[test]
+fn test_memory_claims() {
+    let store = MemoryStore::new();
+    let domains = vec![("http://a.com".to_owned(), 1), ("http://b.com".to_owned(), 2),
+                       ("http://c.com".to_owned(), 1)];
+    assert_eq!(store.store_domains(&domains).unwrap(), 3);
+    // Known domain is not queued twice, only seen more
+    let _ = store.store_domains(&[("http://b.com".to_owned(), 3)]);
+    assert_eq!(store.state.lock().unwrap().domains.len(), 3);
+    assert_eq!(store.state.lock().unwrap().domains["http://b.com"].times_seen, 5);
+
+    assert_eq!(store.claim_domains("w1", 2, 600), vec!["http://a.com", "http://b.com"]);
+    assert_eq!(store.claim_domains("w2", 2, 600), vec!["http://c.com"]);
+    assert!(store.claim_domains("w2", 2, 600).is_empty());
+    assert_eq!(store.extend_lease("http://a.com", "w1", 600).unwrap(), 1);
+    assert_eq!(store.extend_lease("http://c.com", "w1", 600).unwrap(), 0);
+
+    // Claim held by another worker is left alone
+    let _ = store.domain_done("http://c.com", "w1", "completed");
+    assert_eq!(status_of(&store, "http://c.com"), Some("processing"));
+
+    let _ = store.domain_done("http://a.com", "w1", "completed");
+    // Finished domain is nobody's claim anymore
+    assert_eq!(store.extend_lease("http://a.com", "w1", 600).unwrap(), 0);
+    assert!(store.state.lock().unwrap().domains["http://a.com"].lease_until.is_none());
+    assert_eq!(store.state.lock().unwrap().domains["http://a.com"].stop_reason,
+               Some("completed".to_owned()));
+    let _ = store.domain_err("http://b.com", "w1");
+    let _ = store.domain_retry("http://c.com", "w2", 3600, 2);
+    assert_eq!(status_of(&store, "http://a.com"), Some("done"));
+    assert_eq!(status_of(&store, "http://b.com"), Some("invalid"));
+    assert_eq!(status_of(&store, "http://c.com"), Some("new"));
+    for url in &["http://b.com", "http://c.com"] {
+        assert!(store.state.lock().unwrap().domains[*url].claimed_by.is_none());
+    }
+    // Not before the retry delay passes
+    assert!(store.claim_domains("w1", 10, 600).is_empty());
+
+    // Domain that never comes back is given up on
+    store.state.lock().unwrap().domains.get_mut("http://c.com").unwrap().retry_at = Some(0);
+    assert_eq!(store.claim_domains("w1", 10, 600), vec!["http://c.com"]);
+    let _ = store.domain_retry("http://c.com", "w1", 3600, 2);
+    assert_eq!(status_of(&store, "http://c.com"), Some("failed"));
+}
+
+#[test]
+fn test_memory_reaper() {
+    let store = MemoryStore::new();
+    let _ = store.store_domains(&[("http://a.com".to_owned(), 1)]);
+
+    // Lease already expired, worker is gone
+    assert_eq!(store.claim_domains("w1", 1, -1).len(), 1);
+    assert_eq!(store.reap_expired(2).unwrap(), 1);
+    assert_eq!(status_of(&store, "http://a.com"), Some("new"));
+
+    assert_eq!(store.claim_domains("w1", 1, -1).len(), 1);
+    assert_eq!(store.reap_expired(2).unwrap(), 1);
+    assert_eq!(status_of(&store, "http://a.com"), Some("failed"));
+
+    // Live lease is left alone
+    let _ = store.store_domains(&[("http://b.com".to_owned(), 1)]);
+    assert_eq!(store.claim_domains("w1", 1, 600).len(), 1);
+    assert_eq!(store.reap_expired(2).unwrap(), 0);
+}
+
+#[test]
+fn test_live_claims() {
+    let live = LiveClaims::new();
+    live.queued("http://a.com");
+    live.queued("http://b.com");
+    live.progress("http://b.com");
+    live.queued("http://c.com");
+    live.finished("http://c.com");
+
+    let mut alive = live.alive(Duration::from_secs(60));
+    alive.sort();
+    assert_eq!(alive, vec!["http://a.com", "http://b.com"]);
+    // Crawl silent for too long is left to expire, waiting one is not
+    assert_eq!(live.alive(Duration::from_secs(0)), vec!["http://a.com"]);
+}
+
+#[test]
+fn test_memory_store_as_shared() {
+    let store: Arc<CrawlStore> = Arc::new(MemoryStore::new());
+    let record = PageRecord {
+        url: "http://a.com/".to_owned(),
+        etag: None,
+        last_modified: None,
+        content_hash: 1,
+        outlinks: vec!["http://a.com/b".to_owned()],
+        fetched_at: 0,
+    };
+    store.save_page(&record);
+    assert_eq!(store.load_page(&record.url), Some(record));
+
+    let robots = CachedRobots { status: 200, content: "User-agent: *".to_owned(), fetched_at: 0 };
+    store.save_robots("http://a.com", &robots);
+    assert_eq!(store.load_robots("http://a.com"), Some(robots));
+}